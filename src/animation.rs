@@ -0,0 +1,143 @@
+use std::f64::consts::PI;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::ops::{Add, Mul, RangeInclusive, Sub};
+use std::path::{Path, PathBuf};
+use cgmath::{InnerSpace, Vector3};
+use clap::ValueEnum;
+
+use Vector3 as Point3;
+use crate::camera::CameraConfig;
+use crate::util::unit_vector;
+
+/// The camera settings that can change from frame to frame.
+#[derive(Clone, Copy)]
+pub struct Keyframe {
+    pub frame: u32,
+    pub lookfrom: Point3<f64>,
+    pub lookat: Point3<f64>,
+    pub vfov: f64,
+    pub focus_dist: f64
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Interpolation {
+    /// Straight lines between keyframes, changing direction abruptly at each one
+    Linear,
+    /// A smooth curve passing through every keyframe
+    CatmullRom
+}
+
+pub enum Animation {
+    /// Moves through keyframes sorted by frame number
+    Keyframes { keyframes: Vec<Keyframe>, interpolation: Interpolation },
+    /// One full turn of the camera around `lookat`, about the up vector, over `frames` frames
+    Turntable { frames: u32 }
+}
+impl Animation {
+    /// Reads keyframes from a text file with one keyframe per line:
+    /// `frame lookfrom_x lookfrom_y lookfrom_z lookat_x lookat_y lookat_z vfov focus_dist`.
+    ///
+    /// Blank lines and lines starting with `#` are skipped.
+    pub fn open(path: &Path, interpolation: Interpolation) -> Result<Self, Error> {
+        let mut keyframes = vec![];
+        for (number, line) in fs::read_to_string(path)?.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = |message: &str| Error::new(ErrorKind::InvalidData, format!("line {}: {}", number + 1, message));
+            let values = line.split_whitespace().map(|token| token.parse::<f64>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| invalid(&e.to_string()))?;
+            if values.len() != 9 || values[0] < 0.0 || values[0].fract() != 0.0 {
+                return Err(invalid("expected 'frame lookfrom_x lookfrom_y lookfrom_z lookat_x lookat_y lookat_z vfov focus_dist'"));
+            }
+            keyframes.push(Keyframe {
+                frame: values[0] as u32,
+                lookfrom: Point3::new(values[1], values[2], values[3]),
+                lookat: Point3::new(values[4], values[5], values[6]),
+                vfov: values[7],
+                focus_dist: values[8]
+            });
+        }
+        if keyframes.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidData, "the keyframe file has no keyframes"));
+        }
+        keyframes.sort_by_key(|keyframe| keyframe.frame);
+        Ok(Animation::Keyframes { keyframes, interpolation })
+    }
+    /// The frame numbers to render.
+    pub fn frames(self: &Animation) -> RangeInclusive<u32> {
+        match self {
+            Animation::Keyframes { keyframes, .. } => keyframes[0].frame..=keyframes[keyframes.len() - 1].frame,
+            Animation::Turntable { frames } => 1..=*frames
+        }
+    }
+    /// Moves the camera in `config` to where it is at `frame`.
+    pub fn apply(self: &Animation, frame: u32, config: &mut CameraConfig) {
+        match self {
+            Animation::Keyframes { keyframes, interpolation } => {
+                // The keyframes either side of this frame, and their neighbours for the curve
+                let next = keyframes.iter().position(|keyframe| keyframe.frame > frame).unwrap_or(keyframes.len() - 1);
+                let current = next.saturating_sub(1);
+                let k1 = &keyframes[current];
+                let k2 = &keyframes[next];
+                let k0 = &keyframes[current.saturating_sub(1)];
+                let k3 = &keyframes[(next + 1).min(keyframes.len() - 1)];
+                let t = if k2.frame > k1.frame {
+                    ((frame as f64 - k1.frame as f64) / (k2.frame - k1.frame) as f64).clamp(0.0, 1.0)
+                } else {
+                    0.0
+                };
+                let blend = |get: fn(&Keyframe) -> Point3<f64>| match interpolation {
+                    Interpolation::Linear => lerp(get(k1), get(k2), t),
+                    Interpolation::CatmullRom => catmull_rom(get(k0), get(k1), get(k2), get(k3), t)
+                };
+                let blend_scalar = |get: fn(&Keyframe) -> f64| match interpolation {
+                    Interpolation::Linear => lerp(get(k1), get(k2), t),
+                    Interpolation::CatmullRom => catmull_rom(get(k0), get(k1), get(k2), get(k3), t)
+                };
+                config.lookfrom = blend(|keyframe| keyframe.lookfrom);
+                config.lookat = blend(|keyframe| keyframe.lookat);
+                config.vfov = blend_scalar(|keyframe| keyframe.vfov);
+                config.focus_dist = blend_scalar(|keyframe| keyframe.focus_dist);
+            }
+            Animation::Turntable { frames } => {
+                // Rodrigues' rotation of the camera's offset from its target about the up vector
+                let angle = 2.0 * PI * (frame - 1) as f64 / *frames as f64;
+                let axis = unit_vector(config.vup);
+                let offset = config.lookfrom - config.lookat;
+                let rotated = offset * angle.cos()
+                    + axis.cross(offset) * angle.sin()
+                    + axis * axis.dot(offset) * (1.0 - angle.cos());
+                config.lookfrom = config.lookat + rotated;
+            }
+        }
+    }
+}
+
+/// `output` with the frame number added to its file name, so `out.png` becomes `out_0001.png`.
+pub fn frame_path(output: &Path, frame: u32) -> PathBuf {
+    let stem = output.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+    let mut name = format!("{}_{:04}", stem, frame);
+    if let Some(extension) = output.extension() {
+        name = format!("{}.{}", name, extension.to_string_lossy());
+    }
+    output.with_file_name(name)
+}
+
+fn lerp<T>(a: T, b: T, t: f64) -> T
+where T: Copy + Add<Output = T> + Sub<Output = T> + Mul<f64, Output = T> {
+    a + (b - a) * t
+}
+/// Uniform Catmull-Rom spline through `p1` at `t = 0` and `p2` at `t = 1`.
+fn catmull_rom<T>(p0: T, p1: T, p2: T, p3: T, t: f64) -> T
+where T: Copy + Add<Output = T> + Sub<Output = T> + Mul<f64, Output = T> {
+    let t2 = t * t;
+    let t3 = t2 * t;
+    (p1 * 2.0
+        + (p2 - p0) * t
+        + (p0 * 2.0 - p1 * 5.0 + p2 * 4.0 - p3) * t2
+        + (p1 * 3.0 - p0 - p2 * 3.0 + p3) * t3) * 0.5
+}
@@ -0,0 +1,223 @@
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use cgmath::{Vector2, Vector3};
+use clap::ValueEnum;
+use exr::prelude::{AnyChannel, AnyChannels, Encoding, FlatSamples, Image, ImageAttributes, IntegerBounds, Layer, LayerAttributes, SmallVec, WritableImage};
+use image::{Rgb, RgbImage};
+
+use Vector3 as Point3;
+use Vector3 as Color3;
+use Vector2 as Point2;
+use crate::camera::Camera;
+use crate::film::Film;
+use crate::util::{mix_bits, pixel_bytes};
+
+/// A render pass written alongside the beauty image, for compositing and denoising.
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum Aov {
+    /// Distance along the camera ray to the first surface hit, 0 where nothing was hit
+    Depth,
+    /// World position of the first surface hit
+    Position,
+    /// Shading normal of the first surface hit
+    Normal,
+    /// Color of the first surface hit
+    Albedo,
+    /// Surface coordinates of the first surface hit
+    Uv,
+    /// Which object of the scene the pixel shows, 0 being none
+    ObjectId,
+    /// Which material the pixel shows, 0 being none
+    MaterialId,
+    /// Light reflected diffusely by the first surface straight from a light or the sky
+    DiffuseDirect,
+    /// Light reflected diffusely by the first surface after bouncing around the scene
+    DiffuseIndirect,
+    /// Light reflected or refracted specularly by the first surface straight from a light or the sky
+    SpecularDirect,
+    /// Light reflected or refracted specularly by the first surface after bouncing around the scene
+    SpecularIndirect,
+    /// Light emitted by the first surface, or the sky where nothing was hit
+    Emission
+}
+impl Aov {
+    pub fn name(self: &Aov) -> String {
+        self.to_possible_value().unwrap().get_name().replace('-', "_")
+    }
+    /// Ids are taken from a pixel's first sample, since an average of two ids means nothing.
+    /// Everything else is averaged over the pixel's samples.
+    pub fn is_id(self: &Aov) -> bool {
+        matches!(self, Aov::ObjectId | Aov::MaterialId)
+    }
+    /// Names of the pass's channels, which are stored as the first of a color's components.
+    fn channels(self: &Aov) -> &'static [&'static str] {
+        match self {
+            Aov::Depth => &["Z"],
+            Aov::ObjectId | Aov::MaterialId => &["id"],
+            Aov::Uv => &["U", "V"],
+            Aov::Position | Aov::Normal => &["X", "Y", "Z"],
+            _ => &["R", "G", "B"]
+        }
+    }
+    /// Whether the pass holds light, which is exposed and tone mapped like the beauty image.
+    fn is_light(self: &Aov) -> bool {
+        matches!(self, Aov::DiffuseDirect | Aov::DiffuseIndirect | Aov::SpecularDirect | Aov::SpecularIndirect | Aov::Emission)
+    }
+    fn value(self: &Aov, sample: &AovSample) -> Color3<f64> {
+        match self {
+            Aov::Depth => Color3::new(sample.depth, 0.0, 0.0),
+            Aov::Position => sample.position,
+            Aov::Normal => sample.normal,
+            Aov::Albedo => sample.albedo,
+            Aov::Uv => Color3::new(sample.uv.x, sample.uv.y, 0.0),
+            Aov::ObjectId => Color3::new(sample.object_id as f64, 0.0, 0.0),
+            Aov::MaterialId => Color3::new(sample.material_id as f64, 0.0, 0.0),
+            Aov::DiffuseDirect => sample.diffuse_direct,
+            Aov::DiffuseIndirect => sample.diffuse_indirect,
+            Aov::SpecularDirect => sample.specular_direct,
+            Aov::SpecularIndirect => sample.specular_indirect,
+            Aov::Emission => sample.emission
+        }
+    }
+}
+
+/// What one camera ray found at the first surface it hit, and the light it brought back split
+/// up by how it got there.
+pub struct AovSample {
+    pub depth: f64,
+    pub position: Point3<f64>,
+    pub normal: Vector3<f64>,
+    pub albedo: Color3<f64>,
+    pub uv: Point2<f64>,
+    pub object_id: u32,
+    pub material_id: u32,
+    pub diffuse_direct: Color3<f64>,
+    pub diffuse_indirect: Color3<f64>,
+    pub specular_direct: Color3<f64>,
+    pub specular_indirect: Color3<f64>,
+    pub emission: Color3<f64>
+}
+impl AovSample {
+    /// A ray that hit nothing, but may still see `emission` from the sky.
+    pub fn miss(emission: Color3<f64>) -> Self {
+        let zero = Vector3::new(0.0, 0.0, 0.0);
+        AovSample {
+            depth: 0.0,
+            position: zero,
+            normal: zero,
+            albedo: zero,
+            uv: Point2::new(0.0, 0.0),
+            object_id: 0,
+            material_id: 0,
+            diffuse_direct: zero,
+            diffuse_indirect: zero,
+            specular_direct: zero,
+            specular_indirect: zero,
+            emission
+        }
+    }
+}
+
+/// Adds `sample` to a pixel's running totals of the passes in `aovs`. `first` says whether it is
+/// the pixel's first sample.
+pub fn accumulate(aovs: &[Aov], sums: &mut [Color3<f64>], first: bool, sample: &AovSample) {
+    for (aov, sum) in aovs.iter().zip(sums.iter_mut()) {
+        if !aov.is_id() {
+            *sum += aov.value(sample);
+        } else if first {
+            *sum = aov.value(sample);
+        }
+    }
+}
+
+/// Writes the film's passes to `path`: all of them as layers of one EXR, next to a linear beauty
+/// layer, if it ends in .exr, and otherwise each as an 8-bit image of its own with the pass's
+/// name added before the extension, such as out.depth.png.
+pub fn save(camera: &Camera, film: &Film, path: &Path) -> Result<(), Error> {
+    if path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("exr")) {
+        return save_exr(camera, film, path);
+    }
+    for (index, aov) in film.aovs.iter().enumerate() {
+        let image = aov_image(camera, film, index);
+        image.save(pass_path(path, aov)).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+    }
+    Ok(())
+}
+
+/// `path` with the pass's name added before the extension.
+fn pass_path(path: &Path, aov: &Aov) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(extension) => format!("{}.{}.{}", stem, aov.name(), extension.to_string_lossy()),
+        None => format!("{}.{}", stem, aov.name())
+    };
+    path.with_file_name(name)
+}
+
+fn save_exr(camera: &Camera, film: &Film, path: &Path) -> Result<(), Error> {
+    let size = (film.width as usize, film.height as usize);
+    let channel = |name: &str, value: &dyn Fn(i32, i32) -> f64| {
+        let samples = (0..film.height).flat_map(|y| (0..film.width).map(move |x| (x, y)))
+            .map(|(x, y)| value(x, y) as f32)
+            .collect::<Vec<f32>>();
+        AnyChannel::new(name, FlatSamples::F32(samples))
+    };
+    let layer = |name: &str, channels: Vec<AnyChannel<FlatSamples>>| {
+        Layer::new(size, LayerAttributes::named(name), Encoding::FAST_LOSSLESS, AnyChannels::sort(SmallVec::from_vec(channels)))
+    };
+    let beauty = |x, y| camera.exposed(film.get(x, y).mean());
+    let mut layers = vec![layer("beauty", vec![
+        channel("R", &|x, y| beauty(x, y).x),
+        channel("G", &|x, y| beauty(x, y).y),
+        channel("B", &|x, y| beauty(x, y).z)
+    ])];
+    for (index, aov) in film.aovs.iter().enumerate() {
+        let value = |x, y| {
+            let value = film.aov(x, y, index);
+            if aov.is_light() { camera.exposed(value) } else { value }
+        };
+        let channels = aov.channels().iter().enumerate().map(|(component, name)| {
+            channel(name, &|x, y| value(x, y)[component])
+        }).collect();
+        layers.push(layer(&aov.name(), channels));
+    }
+    let image = Image::from_layers(ImageAttributes::new(IntegerBounds::from_dimensions(size)), layers);
+    image.write().to_file(path).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+}
+
+/// A pass made viewable: light developed like the beauty image, depth and position scaled to the
+/// range found in the image, normals and uvs mapped from their range to colors, and ids given
+/// random colors.
+fn aov_image(camera: &Camera, film: &Film, index: usize) -> RgbImage {
+    let aov = film.aovs[index];
+    let values = (0..film.height).flat_map(|y| (0..film.width).map(move |x| (x, y)))
+        .map(|(x, y)| film.aov(x, y, index))
+        .collect::<Vec<Color3<f64>>>();
+    let fold = |start: f64, f: fn(f64, f64) -> f64| values.iter().fold(Color3::new(start, start, start), |a, v| a.zip(*v, f));
+    let (min, max) = (fold(f64::INFINITY, f64::min), fold(f64::NEG_INFINITY, f64::max));
+    RgbImage::from_fn(film.width as u32, film.height as u32, |x, y| {
+        let value = values[(y * film.width as u32 + x) as usize];
+        let encoded = match aov {
+            _ if aov.is_light() => camera.develop_color(value),
+            Aov::Albedo => camera.encode_color(value),
+            // Nearer is brighter, and nothing hit is black
+            Aov::Depth => {
+                let depth = if value.x > 0.0 { 1.0 - value.x / max.x.max(f64::MIN_POSITIVE) } else { 0.0 };
+                Color3::new(depth, depth, depth)
+            }
+            Aov::Position => (value - min).zip(max - min, |v, range| v / range.max(f64::MIN_POSITIVE)),
+            Aov::Normal => value * 0.5 + Color3::new(0.5, 0.5, 0.5),
+            Aov::Uv => value,
+            _ => {
+                let id = value.x as u64;
+                if id == 0 {
+                    Color3::new(0.0, 0.0, 0.0)
+                } else {
+                    let hash = mix_bits(id);
+                    Color3::new((hash & 0xff) as f64, (hash >> 8 & 0xff) as f64, (hash >> 16 & 0xff) as f64) / 255.0
+                }
+            }
+        };
+        Rgb(pixel_bytes(&encoded))
+    })
+}
@@ -0,0 +1,82 @@
+use std::f64::consts::PI;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+use std::sync::Arc;
+use cgmath::Vector2;
+use rand::Rng;
+
+use Vector2 as Point2;
+use crate::util::{mix_bits, random_in_unit_disc};
+
+/// The shape of the lens opening, which is the shape out of focus highlights take.
+#[derive(Clone)]
+pub enum Aperture {
+    Circular,
+    /// A regular polygon of `blades` sides, at least 3, turned by `rotation` degrees
+    Polygon { blades: u32, rotation: f64 },
+    /// Any shape, read from an image where white is open and black is closed
+    Mask(Arc<ApertureMask>)
+}
+
+/// An aperture image prepared for sampling: the running total of its pixels' brightness, row by
+/// row, so a pixel can be picked in proportion to how open it is with a binary search.
+pub struct ApertureMask {
+    width: u32,
+    height: u32,
+    cdf: Vec<f64>
+}
+impl Aperture {
+    pub fn from_image(path: &Path) -> Result<Self, Error> {
+        let mask = image::open(path).map_err(|e| Error::new(ErrorKind::InvalidData, e))?.to_luma32f();
+        let cdf = mask.pixels().scan(0.0, |total, p| {
+            *total += p.0[0].max(0.0) as f64;
+            Some(*total)
+        }).collect::<Vec<f64>>();
+        if cdf.last().copied().unwrap_or(0.0) <= 0.0 {
+            return Err(Error::new(ErrorKind::InvalidData, "the aperture image is completely black"));
+        }
+        Ok(Aperture::Mask(Arc::new(ApertureMask { width: mask.width(), height: mask.height(), cdf })))
+    }
+    /// A number that tells apart apertures of different shapes.
+    pub fn id(self: &Aperture) -> u64 {
+        match self {
+            Aperture::Circular => mix_bits(1),
+            Aperture::Polygon { blades, rotation } => mix_bits(mix_bits(2 ^ *blades as u64) ^ rotation.to_bits()),
+            Aperture::Mask(mask) => mask.cdf.iter().fold(mix_bits(3), |hash, total| mix_bits(hash ^ total.to_bits()))
+        }
+    }
+    /// A uniformly distributed point inside the aperture, which spans `[-1, 1]` on both axes. Points
+    /// in a mask are as dense as the mask is bright.
+    pub fn sample(self: &Aperture, rng: &mut impl Rng) -> Point2<f64> {
+        match self {
+            Aperture::Circular => {
+                let p = random_in_unit_disc(rng);
+                Point2::new(p.x, p.y)
+            }
+            Aperture::Polygon { blades, rotation } => {
+                // Pick one of the equally sized triangles fanning out from the center, then a
+                // uniform point inside it
+                let sector = rng.gen_range(0..*blades) as f64;
+                let step = 2.0 * PI / *blades as f64;
+                let start = rotation.to_radians() + sector * step;
+                let a = Point2::new(start.cos(), start.sin());
+                let b = Point2::new((start + step).cos(), (start + step).sin());
+                let (mut s, mut t): (f64, f64) = (rng.gen(), rng.gen());
+                if s + t > 1.0 {
+                    (s, t) = (1.0 - s, 1.0 - t);
+                }
+                s * a + t * b
+            }
+            Aperture::Mask(mask) => {
+                // Pick a pixel by brightness, then a uniform point inside it
+                let total = mask.cdf[mask.cdf.len() - 1];
+                let target = rng.gen::<f64>() * total;
+                let index = mask.cdf.partition_point(|&c| c <= target).min(mask.cdf.len() - 1);
+                let (i, j) = (index as u32 % mask.width, index as u32 / mask.width);
+                let x = (i as f64 + rng.gen::<f64>()) / mask.width as f64;
+                let y = (j as f64 + rng.gen::<f64>()) / mask.height as f64;
+                Point2::new(2.0 * x - 1.0, 1.0 - 2.0 * y)
+            }
+        }
+    }
+}
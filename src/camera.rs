@@ -1,34 +1,88 @@
 use std::fs::File;
-use std::io::{Error, Write};
-use std::sync::Mutex;
-use cgmath::Vector3;
+use std::io::{Error, ErrorKind, Write};
+use std::time::{Duration, Instant};
+use std::sync::Arc;
+use cgmath::{InnerSpace, Vector2, Vector3};
+use image::{ImageBuffer, Rgb, Rgb32FImage, RgbImage};
 use indicatif::ProgressBar;
-use rand::{Rng, thread_rng};
+use clap::ValueEnum;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
 use rayon::prelude::*;
 use Vector3 as Point3;
 use crate::hittable::HittableList;
 use crate::ray::Ray;
-use crate::util::{random_in_unit_disc, unit_vector, write_pixel};
+use crate::util::{mix_bits, pixel_bytes, unit_vector, write_pixel, Interval};
+use crate::aperture::Aperture;
+use crate::distortion::Distortion;
+use crate::tile::{tiles, Tile, TileOrder};
+use crate::sampler::{Sampler, SamplerKind};
+use crate::film::{Film, FilmPixel};
+use crate::filter::{Filter, FilterKind};
+use crate::tonemap::ToneMapping;
+use crate::color::{ColorSpace, Display};
+use crate::lut::Lut;
+use crate::aov::{self, Aov};
+use crate::debug::{self, RenderMode};
 
 use Vector3 as Color3;
 use crate::image::Image;
 use crate::viewport::Viewport;
 
+#[derive(Clone)]
 pub struct Camera {
     image: Image,
+    projection: Projection,
+    stereo: Option<Stereo>,
+    focus_dist: f64,
+    /// Multiplies radiance before it is written out
+    exposure: f64,
+    tone_mapping: ToneMapping,
+    working_space: ColorSpace,
+    display: Display,
+    lut: Option<Arc<Lut>>,
+    /// Sideways offset of the eye for omni-directional stereo panoramas
+    eye_offset: f64,
     camera_center: Point3<f64>,
+    u: Vector3<f64>,
+    v: Vector3<f64>,
+    w: Vector3<f64>,
     pixel00_loc: Point3<f64>,
     pixel_delta_u: Vector3<f64>,
     pixel_delta_v: Vector3<f64>,
     defocus_disc_u: Vector3<f64>,
     defocus_disc_v: Vector3<f64>,
     defocus_angle: f64,
+    aperture: Aperture,
+    optical_vignetting: f64,
+    /// Normal of the plane in focus, which leans away from the view direction when the lens is tilted
+    focus_plane_normal: Vector3<f64>,
+    distortion: Option<Distortion>,
+    crop: Option<Crop>,
+    tile_size: i32,
+    tile_order: TileOrder,
+    seed: u64,
+    sampler: SamplerKind,
+    adaptive: Option<AdaptiveSampling>,
+    filter: Filter,
+    aovs: Vec<Aov>,
+    mode: RenderMode,
     max_ray_bounce_depth: i32
 }
 impl Camera {
-    pub fn initialize(config: CameraConfig) -> Self {
+    pub fn initialize(mut config: CameraConfig) -> Self {
+        // A physical camera description takes over the field of view and the aperture
+        let exposure = match config.physical {
+            Some(physical) => {
+                config.vfov = physical.vfov();
+                config.defocus_angle = physical.defocus_angle(config.focus_dist);
+                physical.exposure()
+            }
+            None => 1.0
+        } * f64::powf(2.0, config.exposure_compensation);
+
         // Init image and viewport
-        let image = Image::new(config.image_width, config.aspect_ratio);
+        let image = Image::new(config.image_width, config.aspect_ratio, config.samples_per_pixel);
         let viewport = Viewport::new(&image, &config);
 
         // Camera vector space
@@ -38,87 +92,875 @@ impl Camera {
         let v = w.cross(u);
 
         // Viewport edge vectors
-        let viewport_u = viewport.width * u;
-        let viewport_v = viewport.height * -v;
+        let (viewport_u, viewport_v) = match config.projection {
+            // Parallel rays leave from a view-sized rectangle around the camera instead
+            Projection::Orthographic { view_width } => {
+                let view_height = view_width * (image.height as f64 / image.width as f64);
+                (view_width * u, view_height * -v)
+            }
+            _ => (viewport.width * u, viewport.height * -v)
+        };
 
         // Deltas between horizontal & vertical vectors
         let pixel_delta_u = viewport_u / image.width as f64;
         let pixel_delta_v = viewport_v / image.height as f64;
 
         // Location of upper left pixel
+        let viewport_distance = match config.projection {
+            Projection::Orthographic { .. } => 0.0,
+            _ => config.focus_dist
+        };
         let viewport_upper_left = camera_center
-            - (config.focus_dist * w)
+            - (viewport_distance * w)
             - (viewport_u/2.0)
             - (viewport_v/2.0);
-        let pixel00_loc = viewport_upper_left + 0.5 * (pixel_delta_u + pixel_delta_v);
+        let mut pixel00_loc = viewport_upper_left + 0.5 * (pixel_delta_u + pixel_delta_v);
+
+        // Shifting the lens slides the image across the sensor without turning the camera, and
+        // tilting it turns the plane in focus instead of keeping it square to the view direction
+        let mut focus_plane_normal = w;
+        if let Some(tilt_shift) = config.tilt_shift {
+            pixel00_loc += tilt_shift.shift_x * viewport_u - tilt_shift.shift_y * viewport_v;
+            focus_plane_normal = unit_vector(
+                w + tilt_shift.tilt.to_radians().tan() * v + tilt_shift.swing.to_radians().tan() * u
+            );
+        }
 
         let defocus_radius = config.focus_dist * (config.defocus_angle / 2.0).to_radians().tan();
         let defocus_disc_u = u * defocus_radius;
         let defocus_disc_v = v * defocus_radius;
 
+        // Keep the crop window inside the image
+        let crop = config.crop.map(|crop| {
+            let (x0, y0) = (crop.x0.clamp(0, image.width), crop.y0.clamp(0, image.height));
+            Crop { x0, y0, x1: crop.x1.clamp(x0, image.width), y1: crop.y1.clamp(y0, image.height), ..crop }
+        });
+
         Camera {
             image,
+            projection: config.projection,
+            stereo: config.stereo,
+            focus_dist: config.focus_dist,
+            exposure,
+            tone_mapping: config.tone_mapping,
+            working_space: config.working_space,
+            display: config.display,
+            lut: config.lut,
+            eye_offset: 0.0,
             camera_center,
+            u,
+            v,
+            w,
             pixel00_loc,
             pixel_delta_u,
             pixel_delta_v,
             defocus_disc_u,
             defocus_disc_v,
             defocus_angle: config.defocus_angle,
+            aperture: config.aperture,
+            optical_vignetting: config.optical_vignetting,
+            focus_plane_normal,
+            distortion: config.distortion,
+            crop,
+            tile_size: config.tile_size,
+            tile_order: config.tile_order,
+            seed: config.seed,
+            sampler: config.sampler,
+            adaptive: config.adaptive,
+            filter: config.filter,
+            aovs: config.aovs,
+            mode: config.mode,
             max_ray_bounce_depth: config.max_depth
         }
     }
-    pub fn render(self: &Camera, file: &mut File, hittables: &HittableList) -> Result<(), Error> {
-        file.write_all(format!("P3\n{0} {1}\n255\n", self.image.width, self.image.height).as_bytes())?;
-        let progress = Mutex::new(ProgressBar::new(self.image.height as u64));
-        let pixels = (0..self.image.height).into_par_iter().map(|j| {
-            let row = (0..self.image.width).into_par_iter().map(move |i| {
-                (0..self.image.samples_per_pixel).into_par_iter().map(|_| {
-                    let ray = self.get_ray(i, j);
-                    ray.color(hittables, self.max_ray_bounce_depth)
-                }).sum()
-            }).collect::<Vec<Color3<f64>>>();
-            progress.lock().unwrap().inc(1);
-            row
-        }).collect::<Vec<Vec<Color3<f64>>>>();
-        for pixel_row in pixels {
-            for pixel_color in pixel_row {
-                write_pixel(file, &pixel_color, self.image.samples_per_pixel)?;
+    /// A hash of every setting that decides what a sample comes out as, to tell whether a render
+    /// can be carried on with this camera. Settings applied to the finished image, such as the
+    /// exposure, are left out, and so is the sample count, which a resumed render may raise,
+    /// except with the stratified sampler, whose strata depend on it.
+    pub fn fingerprint(self: &Camera) -> u64 {
+        let vector = |v: Vector3<f64>| [v.x.to_bits(), v.y.to_bits(), v.z.to_bits()];
+        let projection = match self.projection {
+            Projection::Perspective => [0, 0, 0],
+            Projection::Orthographic { view_width } => [1, view_width.to_bits(), 0],
+            Projection::Fisheye { fov, mapping } => [2, fov.to_bits(), mapping as u64],
+            Projection::Equirectangular => [3, 0, 0]
+        };
+        let stereo = self.stereo.map_or([0; 3], |stereo| {
+            [stereo.layout as u64 + 1, stereo.interocular_distance.to_bits(), stereo.convergence_distance.map_or(0, f64::to_bits)]
+        });
+        let distortion = self.distortion.map_or([0; 5], |d| [d.k1, d.k2, d.k3, d.p1, d.p2].map(f64::to_bits));
+        let window = self.window();
+        let samples = match self.sampler {
+            SamplerKind::Stratified => self.max_samples() as u64,
+            _ => 0
+        };
+        let settings = [
+            [self.camera_center, self.u, self.v, self.w, self.pixel00_loc, self.pixel_delta_u, self.pixel_delta_v].map(vector).concat(),
+            [self.defocus_disc_u, self.defocus_disc_v, self.focus_plane_normal].map(vector).concat(),
+            projection.to_vec(),
+            stereo.to_vec(),
+            distortion.to_vec(),
+            [window.x0, window.y0, window.x1, window.y1, window.full_frame as i32].map(|n| n as u64).to_vec(),
+            vec![
+                self.image.width as u64,
+                self.image.height as u64,
+                self.focus_dist.to_bits(),
+                self.defocus_angle.to_bits(),
+                self.aperture.id(),
+                self.optical_vignetting.to_bits(),
+                self.sampler as u64,
+                samples,
+                self.filter.kind as u64,
+                self.filter.radius.to_bits(),
+                self.working_space as u64,
+                self.mode as u64,
+                self.max_ray_bounce_depth as u64
+            ],
+            self.aovs.iter().map(|&aov| aov as u64).collect()
+        ].concat();
+        settings.iter().fold(mix_bits(0), |hash, &bits| mix_bits(hash ^ bits))
+    }
+    /// Blank films to render into, one for each eye.
+    pub fn new_films(self: &Camera) -> Vec<Film> {
+        let (width, height) = self.frame_size();
+        vec![Film::with_aovs(width, height, self.aovs.clone()); self.eyes().len()]
+    }
+    /// Whether `films` could have come from `new_films`, so a render can carry on with them.
+    pub fn fits(self: &Camera, films: &[Film]) -> bool {
+        let (width, height) = self.frame_size();
+        films.len() == self.eyes().len()
+            && films.iter().all(|film| film.width == width && film.height == height && film.aovs == self.aovs)
+    }
+    /// Samples every pixel of `films` until it has `samples_per_pixel` samples or is smooth enough.
+    /// The films may already hold samples, which are kept and added to.
+    pub fn render(self: &Camera, hittables: &HittableList, films: &mut [Film]) {
+        for (eye, film) in self.eyes().iter().zip(films.iter_mut()) {
+            eye.render_film(hittables, film);
+        }
+    }
+    /// Like `render`, but in batches of tiles, handing the films so far to `flush` after a batch
+    /// once `flush_interval` has passed since the last time, so a long render can be saved as it
+    /// goes. The result is the same as `render`'s.
+    pub fn render_in_batches(
+        self: &Camera,
+        hittables: &HittableList,
+        films: &mut [Film],
+        flush_interval: Duration,
+        mut flush: impl FnMut(&[Film]) -> Result<(), Error>
+    ) -> Result<(), Error> {
+        let mut last_flush = Instant::now();
+        for (index, eye) in self.eyes().iter().enumerate() {
+            let tiles = eye.window_tiles();
+            let progress = ProgressBar::new(tiles.len() as u64);
+            // Enough tiles to keep every thread busy between flushes
+            for batch in tiles.chunks(rayon::current_num_threads() * 4) {
+                eye.render_tiles(hittables, &mut films[index], batch, &progress);
+                if last_flush.elapsed() >= flush_interval {
+                    flush(films)?;
+                    last_flush = Instant::now();
+                }
             }
+            progress.finish();
         }
         Ok(())
     }
-    fn get_ray(self: &Camera, i: i32, j: i32) -> Ray {
-        let pixel_center = self.pixel00_loc + (i as f64 * self.pixel_delta_u) + (j as f64 * self.pixel_delta_v);
-        let pixel_sample = pixel_center + self.pixel_sample_square();
-
-        let origin = if self.defocus_angle <= 0.0 { self.camera_center } else { self.defocus_disc_sample() };
-        let direction = pixel_sample - origin;
-        let time = thread_rng().gen_range(0.0..1.0);
-        Ray { origin, direction, time }
+    /// Like `render`, but in passes of one sample per pixel, handing the films so far to `flush`
+    /// every `flush_interval`, and stopping early if `time_budget` runs out.
+    ///
+    /// Since each pixel's samples are numbered the same way either way, the result matches
+    /// `render` with the same number of samples, up to rounding where filters overlap pixels.
+    pub fn render_progressive(
+        self: &Camera,
+        hittables: &HittableList,
+        films: &mut [Film],
+        time_budget: Option<Duration>,
+        flush_interval: Duration,
+        mut flush: impl FnMut(&[Film]) -> Result<(), Error>
+    ) -> Result<(), Error> {
+        let start = Instant::now();
+        let mut last_flush = start;
+        let eyes = self.eyes();
+        let progress = ProgressBar::new(self.max_samples() as u64);
+        progress.set_position(self.passes_done(films) as u64);
+        while !self.finished(films) && time_budget.is_none_or(|budget| start.elapsed() < budget) {
+            for (eye, film) in eyes.iter().zip(films.iter_mut()) {
+                eye.render_pass(hittables, film);
+            }
+            progress.set_position(self.passes_done(films) as u64);
+            if last_flush.elapsed() >= flush_interval {
+                flush(films)?;
+                last_flush = Instant::now();
+            }
+        }
+        progress.finish();
+        Ok(())
+    }
+    /// Whether every pixel of the window has all its samples or is smooth enough.
+    fn finished(self: &Camera, films: &[Film]) -> bool {
+        let (origin_x, origin_y) = self.origin();
+        films.iter().all(|film| self.window_pixels().all(|(i, j)| {
+            let pixel = film.get(i - origin_x, j - origin_y);
+            pixel.samples >= self.max_samples() || self.converged(pixel)
+        }))
+    }
+    /// The fewest samples any unfinished pixel of the window has.
+    fn passes_done(self: &Camera, films: &[Film]) -> u32 {
+        let (origin_x, origin_y) = self.origin();
+        films.iter().flat_map(|film| self.window_pixels().map(move |(i, j)| film.get(i - origin_x, j - origin_y)))
+            .filter(|pixel| !self.converged(pixel))
+            .map(|pixel| pixel.samples.min(self.max_samples()))
+            .min()
+            .unwrap_or(self.max_samples())
+    }
+    /// The cameras the frame is rendered from: this one, or one for each eye.
+    fn eyes(self: &Camera) -> Vec<Camera> {
+        match self.stereo {
+            None => vec![self.clone()],
+            Some(stereo) => {
+                let half = stereo.interocular_distance / 2.0;
+                let convergence = stereo.convergence_distance.unwrap_or(self.focus_dist);
+                vec![self.eye(-half, convergence), self.eye(half, convergence)]
+            }
+        }
+    }
+    /// Lays out the films rendered from each eye in one frame.
+    pub fn compose(self: &Camera, films: &[Film]) -> Film {
+        match (self.stereo, films) {
+            (Some(stereo), [left, right]) => match stereo.layout {
+                StereoLayout::SideBySide => Film::beside(left, right),
+                StereoLayout::TopBottom => Film::above(left, right)
+            },
+            _ => films[0].clone()
+        }
     }
-    fn pixel_sample_square(self: &Camera) -> Vector3<f64> {
-        let mut rng = rand::thread_rng();
+    /// Writes `film` out as a plain PPM.
+    pub fn write_ppm(self: &Camera, film: &Film, file: &mut File) -> Result<(), Error> {
+        file.write_all(format!("P3\n{0} {1}\n255\n", film.width, film.height).as_bytes())?;
+        for color in self.develop_film(film) {
+            write_pixel(file, &color)?;
+        }
+        Ok(())
+    }
+    /// `film` as an 8-bit image, for saving in formats other than plain PPM.
+    pub fn to_image(self: &Camera, film: &Film) -> RgbImage {
+        let colors = self.develop_film(film);
+        RgbImage::from_fn(film.width as u32, film.height as u32, |x, y| {
+            Rgb(pixel_bytes(&colors[(y * film.width as u32 + x) as usize]))
+        })
+    }
+    /// The colors of `film`'s pixels encoded for the display, row by row. Debug views are shown
+    /// as they are, without the exposure and tone mapping meant for light.
+    fn develop_film(self: &Camera, film: &Film) -> Vec<Color3<f64>> {
+        let means = film.pixels.iter().map(FilmPixel::mean);
+        match self.mode {
+            RenderMode::Beauty => means.map(|color| self.develop_color(color)).collect(),
+            mode => debug::normalize(mode, &means.collect::<Vec<_>>(), |color| self.display.encode(color))
+        }
+    }
+    /// Radiance in the working space encoded for the display: exposed, tone mapped, converted to
+    /// the display's primaries and transfer function, and graded with the LUT if there is one.
+    pub fn develop_color(self: &Camera, color: Color3<f64>) -> Color3<f64> {
+        let linear = self.working_space.to_linear_srgb(self.exposed(color));
+        let encoded = self.display.encode(self.tone_mapping.apply(linear));
+        match &self.lut {
+            Some(lut) => lut.apply(encoded),
+            None => encoded
+        }
+    }
+    /// A color in the working space, such as an albedo, encoded for the display as it is.
+    pub fn encode_color(self: &Camera, color: Color3<f64>) -> Color3<f64> {
+        self.display.encode(self.working_space.to_linear_srgb(color))
+    }
+    /// Radiance in the working space with the camera's exposure applied.
+    pub fn exposed(self: &Camera, color: Color3<f64>) -> Color3<f64> {
+        color * self.exposure
+    }
+    /// The most samples any pixel takes.
+    pub fn max_samples(self: &Camera) -> u32 {
+        self.image.samples_per_pixel
+    }
+    /// Size of the image one eye produces: the crop window, unless the full frame is kept.
+    fn frame_size(self: &Camera) -> (i32, i32) {
+        match self.crop {
+            Some(crop) if !crop.full_frame => (crop.x1 - crop.x0, crop.y1 - crop.y0),
+            _ => (self.image.width, self.image.height)
+        }
+    }
+    /// The pixels that are rendered: the crop window, or the whole image.
+    fn window(self: &Camera) -> Crop {
+        match self.crop {
+            Some(crop) => crop,
+            None => Crop { x0: 0, y0: 0, x1: self.image.width, y1: self.image.height, full_frame: false }
+        }
+    }
+    /// An error if the crop window, once kept inside the image, has no pixels left.
+    pub fn check_window(self: &Camera) -> Result<(), Error> {
+        let window = self.window();
+        if window.x0 >= window.x1 || window.y0 >= window.y1 {
+            let message = format!("--crop: the window has no pixels inside the {}x{} image", self.image.width, self.image.height);
+            return Err(Error::new(ErrorKind::InvalidInput, message));
+        }
+        Ok(())
+    }
+    fn window_pixels(self: &Camera) -> impl Iterator<Item = (i32, i32)> {
+        let window = self.window();
+        (window.y0..window.y1).flat_map(move |j| (window.x0..window.x1).map(move |i| (i, j)))
+    }
+    /// Image coordinates of the film's upper left pixel.
+    fn origin(self: &Camera) -> (i32, i32) {
+        let window = self.window();
+        if window.full_frame { (0, 0) } else { (window.x0, window.y0) }
+    }
+    /// Samples every pixel of one eye's image. Pixels outside the crop window are left out, or
+    /// black if the full frame is kept.
+    fn render_film(self: &Camera, hittables: &HittableList, film: &mut Film) {
+        let tiles = self.window_tiles();
+        let progress = ProgressBar::new(tiles.len() as u64);
+        self.render_tiles(hittables, film, &tiles, &progress);
+        progress.finish();
+    }
+    /// Samples every pixel of `tiles` until it is done.
+    fn render_tiles(self: &Camera, hittables: &HittableList, film: &mut Film, tiles: &[Tile], progress: &ProgressBar) {
+        self.update_pixels(film, tiles, progress, |i, j, pixel, aov_sums, samples| {
+            while pixel.samples < self.max_samples() && !self.converged(pixel) {
+                samples.push(self.add_sample(i, j, pixel, aov_sums, hittables));
+            }
+        });
+    }
+    /// Adds one more sample to every pixel that isn't done yet.
+    fn render_pass(self: &Camera, hittables: &HittableList, film: &mut Film) {
+        self.update_pixels(film, &self.window_tiles(), &ProgressBar::hidden(), |i, j, pixel, aov_sums, samples| {
+            if pixel.samples < self.max_samples() && !self.converged(pixel) {
+                samples.push(self.add_sample(i, j, pixel, aov_sums, hittables));
+            }
+        });
+    }
+    /// The tiles the window is split into, in `tile_order`.
+    fn window_tiles(self: &Camera) -> Vec<Tile> {
+        let window = self.window();
+        tiles(window.x0, window.y0, window.x1, window.y1, self.tile_size, self.tile_order)
+    }
+    /// Lets `update` take samples in every pixel of `tiles` in `film`, and splats them into the
+    /// pixels around them.
+    ///
+    /// Threads take the tiles in order, each updating all of a tile's pixels before taking the
+    /// next. A tile's samples are splatted into a film of its own reaching the filter radius past
+    /// its edges, and those are added up in tile order afterwards. Every sample draws from its own
+    /// random number stream, so the result doesn't depend on which thread rendered what.
+    fn update_pixels(
+        self: &Camera,
+        film: &mut Film,
+        tiles: &[Tile],
+        progress: &ProgressBar,
+        update: impl Fn(i32, i32, &mut FilmPixel, &mut [Color3<f64>], &mut Vec<(f64, f64, Color3<f64>)>) + Sync
+    ) {
+        let window = self.window();
+        let (origin_x, origin_y) = self.origin();
+        let margin = self.filter.radius.ceil() as i32 + 1;
+        let current: &Film = film;
+        let mut updated = tiles.iter().copied().enumerate().par_bridge().map(|(index, tile)| {
+            let mut samples = vec![];
+            let pixels = tile.pixels().map(|(i, j)| {
+                let mut pixel = *current.get(i - origin_x, j - origin_y);
+                let mut aov_sums = current.aov_sums(i - origin_x, j - origin_y).to_vec();
+                update(i, j, &mut pixel, &mut aov_sums, &mut samples);
+                (pixel, aov_sums)
+            }).collect::<Vec<_>>();
+            let (x0, y0) = (tile.x0 - margin, tile.y0 - margin);
+            let mut splats = Film::new(tile.x1 - x0 + margin, tile.y1 - y0 + margin);
+            for (x, y, color) in samples {
+                self.filter.splat(&mut splats, x - x0 as f64, y - y0 as f64, color);
+            }
+            progress.inc(1);
+            (index, tile, pixels, splats)
+        }).collect::<Vec<_>>();
+        updated.sort_by_key(|(index, ..)| *index);
+        for (_, tile, pixels, _) in &updated {
+            for ((i, j), (pixel, aov_sums)) in tile.pixels().zip(pixels) {
+                let film_pixel = film.get_mut(i - origin_x, j - origin_y);
+                *film_pixel = FilmPixel { sum: film_pixel.sum, weight: film_pixel.weight, ..*pixel };
+                film.aov_sums_mut(i - origin_x, j - origin_y).copy_from_slice(aov_sums);
+            }
+        }
+        for (_, tile, _, splats) in &updated {
+            let (x0, y0) = (tile.x0 - margin, tile.y0 - margin);
+            for y in 0..splats.height {
+                for x in 0..splats.width {
+                    let (i, j) = (x0 + x, y0 + y);
+                    if i < window.x0 || i >= window.x1 || j < window.y0 || j >= window.y1 {
+                        continue;
+                    }
+                    let splat = splats.get(x, y);
+                    let film_pixel = film.get_mut(i - origin_x, j - origin_y);
+                    film_pixel.sum += splat.sum;
+                    film_pixel.weight += splat.weight;
+                }
+            }
+        }
+    }
+    /// Whether adaptive sampling considers `pixel` smooth enough to stop.
+    fn converged(self: &Camera, pixel: &FilmPixel) -> bool {
+        match self.adaptive {
+            Some(adaptive) => pixel.samples >= adaptive.min_samples && pixel.error(self.exposure) < adaptive.noise_threshold,
+            None => false
+        }
+    }
+    /// Traces the next sample of pixel `(i, j)`, counting it in `pixel` and its passes in
+    /// `aov_sums`. Returns where in the image it was taken and its color, for splatting.
+    fn add_sample(
+        self: &Camera,
+        i: i32,
+        j: i32,
+        pixel: &mut FilmPixel,
+        aov_sums: &mut [Color3<f64>],
+        hittables: &HittableList
+    ) -> (f64, f64, Color3<f64>) {
+        let mut rng = self.sample_rng(i, j, pixel.samples as i32);
+        let (px, py) = self.pixel_sample_square(&mut rng);
+        let (x, y) = (i as f64 + 0.5 + px, j as f64 + 0.5 + py);
+        let color = match self.get_ray(x, y, &mut rng) {
+            Some(ray) if self.mode != RenderMode::Beauty => {
+                debug::shade(self.mode, &ray, hittables, self.max_ray_bounce_depth, |distance| self.pixel_size(distance), &mut rng)
+            }
+            Some(ray) if !self.aovs.is_empty() => {
+                let (color, sample) = ray.color_with_aovs(hittables, self.max_ray_bounce_depth, self.working_space, &mut rng);
+                aov::accumulate(&self.aovs, aov_sums, pixel.samples == 0, &sample);
+                color
+            }
+            Some(ray) => ray.color(hittables, self.max_ray_bounce_depth, self.working_space, &mut rng),
+            None => Color3::new(0.0, 0.0, 0.0)
+        };
+        pixel.add(color);
+        (x, y, color)
+    }
+    /// Roughly how wide a pixel is `distance` away from the camera.
+    fn pixel_size(self: &Camera, distance: f64) -> f64 {
+        let pixel_angle = match self.projection {
+            Projection::Perspective => self.pixel_delta_u.magnitude() / self.focus_dist,
+            Projection::Orthographic { .. } => return self.pixel_delta_u.magnitude(),
+            Projection::Fisheye { fov, .. } => (fov / 2.0).to_radians() / (self.image.height as f64 / 2.0),
+            Projection::Equirectangular => 2.0 * std::f64::consts::PI / self.image.width as f64
+        };
+        pixel_angle * distance
+    }
+    /// Distance to whatever is seen through the center of pixel `(i, j)`: along the view direction
+    /// for the perspective and orthographic projections, and along the ray for the others. `None`
+    /// if nothing is seen there, and an error if the pixel is not in the image.
+    pub fn depth_at(self: &Camera, i: i32, j: i32, hittables: &HittableList) -> Result<Option<f64>, Error> {
+        if i < 0 || i >= self.image.width || j < 0 || j >= self.image.height {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("pixel {},{} is outside the {}x{} image", i, j, self.image.width, self.image.height)
+            ));
+        }
+        // The ray through the center of the lens, which does not depend on the focus
+        let mut pinhole = self.clone();
+        pinhole.defocus_angle = 0.0;
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let Some(ray) = pinhole.get_ray(i as f64 + 0.5, j as f64 + 0.5, &mut rng) else {
+            return Ok(None);
+        };
+        let Some(hit) = hittables.hit(&ray, Interval::new(0.001, f64::INFINITY), &mut rng) else {
+            return Ok(None);
+        };
+        Ok(Some(match self.projection {
+            Projection::Perspective | Projection::Orthographic { .. } => (hit.point - self.camera_center).dot(-self.w),
+            _ => hit.t * ray.direction.magnitude()
+        }))
+    }
+    /// This camera moved `offset` along its horizontal axis, with the image shifted so that
+    /// objects `convergence` away from the camera line up in both eyes.
+    fn eye(self: &Camera, offset: f64, convergence: f64) -> Camera {
+        let mut eye = self.clone();
+        match self.projection {
+            Projection::Perspective => {
+                // Off-axis frustum: the eye moves but its image only moves by the part of the
+                // offset that is left at the focus plane, keeping the optical axes parallel
+                eye.camera_center += offset * self.u;
+                eye.pixel00_loc += offset * (1.0 - self.focus_dist / convergence) * self.u;
+            }
+            Projection::Orthographic { .. } => {
+                eye.pixel00_loc += offset * self.u;
+            }
+            Projection::Fisheye { .. } => {
+                eye.camera_center += offset * self.u;
+            }
+            // Each ray of the panorama leaves from its own point on the circle the eyes turn on
+            Projection::Equirectangular => {
+                eye.eye_offset = offset;
+            }
+        }
+        eye
+    }
+    /// A ray through the point `(x, y)` of the image, measured in pixels from its upper left
+    /// corner, or `None` if the point lies outside the projection's image circle.
+    fn get_ray(self: &Camera, x: f64, y: f64, rng: &mut impl Rng) -> Option<Ray> {
+        let time = rng.gen_range(0.0..1.0);
+        match self.projection {
+            Projection::Perspective => {
+                let pixel_sample = self.viewport_point(x, y);
+                let origin = if self.defocus_angle <= 0.0 {
+                    self.camera_center
+                } else {
+                    // Position in the frame, from the center (0) to the corners (1)
+                    let half_width = self.image.width as f64 / 2.0;
+                    let half_height = self.image.height as f64 / 2.0;
+                    let half_diagonal = f64::sqrt(half_width * half_width + half_height * half_height);
+                    let frame_x = (x - half_width) / half_diagonal;
+                    let frame_y = (half_height - y) / half_diagonal;
+                    self.defocus_disc_sample(frame_x, frame_y, rng)?
+                };
+                let direction = match self.focus_point(pixel_sample) {
+                    Some(focus_point) => focus_point - origin,
+                    // The plane in focus leans so far that this pixel never reaches it
+                    None => pixel_sample - self.camera_center
+                };
+                Some(Ray { origin, direction, time })
+            }
+            Projection::Orthographic { .. } => {
+                let origin = self.pixel00_loc + ((x - 0.5) * self.pixel_delta_u) + ((y - 0.5) * self.pixel_delta_v);
+                Some(Ray { origin, direction: -self.w, time })
+            }
+            Projection::Fisheye { fov, mapping } => {
+                // Position relative to the image center, in units of half the image height
+                let half_height = self.image.height as f64 / 2.0;
+                let x = (x - self.image.width as f64 / 2.0) / half_height;
+                let y = (half_height - y) / half_height;
+                let r = f64::sqrt(x * x + y * y);
+                // The image circle touches the top and bottom edges, and beyond it the lens sees nothing
+                if r > 1.0 {
+                    return None;
+                }
+                let half_fov = (fov / 2.0).to_radians();
+                // Angle away from the view direction, scaled so the top and bottom edges see fov / 2
+                let theta = match mapping {
+                    FisheyeMapping::Equidistant => r * half_fov,
+                    FisheyeMapping::Equisolid => {
+                        let focal_length = 1.0 / (2.0 * (half_fov / 2.0).sin());
+                        2.0 * f64::asin(r / (2.0 * focal_length))
+                    }
+                };
+                if theta > std::f64::consts::PI {
+                    return None;
+                }
+                let (cos_phi, sin_phi) = if r > 0.0 { (x / r, y / r) } else { (1.0, 0.0) };
+                let direction = theta.cos() * -self.w + theta.sin() * (cos_phi * self.u + sin_phi * self.v);
+                Some(Ray { origin: self.camera_center, direction, time })
+            }
+            Projection::Equirectangular => {
+                let longitude = (x / self.image.width as f64 - 0.5) * 2.0 * std::f64::consts::PI;
+                let latitude = (0.5 - y / self.image.height as f64) * std::f64::consts::PI;
+                let direction = latitude.cos() * (longitude.sin() * self.u + longitude.cos() * -self.w) + latitude.sin() * self.v;
+                let right = longitude.cos() * self.u - longitude.sin() * -self.w;
+                Some(Ray { origin: self.camera_center + self.eye_offset * right, direction, time })
+            }
+        }
+    }
+    /// The point on the viewport seen at pixel position `(x, y)`, measured from the upper left
+    /// corner of the image, once the lens distortion has been taken out.
+    fn viewport_point(self: &Camera, x: f64, y: f64) -> Point3<f64> {
+        let point = self.pixel00_loc + ((x - 0.5) * self.pixel_delta_u) + ((y - 0.5) * self.pixel_delta_v);
+        match &self.distortion {
+            None => point,
+            Some(distortion) => {
+                let axis = self.camera_center - self.focus_dist * self.w;
+                let offset = (point - axis) / self.focus_dist;
+                let (x, y) = distortion.undistort(offset.dot(self.u), offset.dot(self.v));
+                axis + self.focus_dist * (x * self.u + y * self.v)
+            }
+        }
+    }
+    /// Where the line of sight from the camera center through `viewport_point` crosses the plane
+    /// in focus. Without tilt that is `viewport_point` itself.
+    fn focus_point(self: &Camera, viewport_point: Point3<f64>) -> Option<Point3<f64>> {
+        let direction = viewport_point - self.camera_center;
+        let on_plane = self.camera_center - self.focus_dist * self.w;
+        let s = (on_plane - self.camera_center).dot(self.focus_plane_normal) / direction.dot(self.focus_plane_normal);
+        if s > 0.0 && s.is_finite() { Some(self.camera_center + s * direction) } else { None }
+    }
+    /// Resamples a photographed plate, taken through this camera's lens, into the undistorted
+    /// image an ideal pinhole camera would have seen, so renders without distortion line up with it.
+    pub fn undistort_plate(self: &Camera, plate: &Rgb32FImage) -> Rgb32FImage {
+        let (width, height) = plate.dimensions();
+        let scale_x = self.image.width as f64 / width as f64;
+        let scale_y = self.image.height as f64 / height as f64;
+        let axis = self.camera_center - self.focus_dist * self.w;
+        ImageBuffer::from_fn(width, height, |x, y| {
+            // Find the undistorted point this pixel stands for, then where the lens put it
+            let point = self.pixel00_loc
+                + (((x as f64 + 0.5) * scale_x - 0.5) * self.pixel_delta_u)
+                + (((y as f64 + 0.5) * scale_y - 0.5) * self.pixel_delta_v);
+            let offset = (point - axis) / self.focus_dist;
+            let (dx, dy) = match &self.distortion {
+                Some(distortion) => distortion.distort(offset.dot(self.u), offset.dot(self.v)),
+                None => (offset.dot(self.u), offset.dot(self.v))
+            };
+            let distorted = axis + self.focus_dist * (dx * self.u + dy * self.v) - self.pixel00_loc;
+            let plate_x = (distorted.dot(self.pixel_delta_u) / self.pixel_delta_u.magnitude2() + 0.5) / scale_x;
+            let plate_y = (distorted.dot(self.pixel_delta_v) / self.pixel_delta_v.magnitude2() + 0.5) / scale_y;
+            sample_bilinear(plate, plate_x - 0.5, plate_y - 0.5)
+        })
+    }
+    /// The random numbers for sample number `sample` of pixel `(i, j)`.
+    fn sample_rng(self: &Camera, i: i32, j: i32, sample: i32) -> Sampler {
+        Sampler::new(self.sampler, self.seed, (i as u32, j as u32), sample as u32, self.image.samples_per_pixel)
+    }
+    /// A random offset within a pixel, in pixels.
+    fn pixel_sample_square(self: &Camera, rng: &mut impl Rng) -> (f64, f64) {
         let px = rng.gen_range(-0.5..0.5);
         let py = rng.gen_range(-0.5..0.5);
-        (px * self.pixel_delta_u) + (py * self.pixel_delta_v)
+        (px, py)
     }
-    fn defocus_disc_sample(self: &Camera) -> Point3<f64> {
-        let p = random_in_unit_disc();
-        self.camera_center + (p.x * self.defocus_disc_u) + (p.y * self.defocus_disc_v)
-
+    /// A point on the lens, or `None` if the lens barrel blocks it for a pixel at `(frame_x, frame_y)`.
+    ///
+    /// Off axis, the opening is seen through the barrel at an angle, so only the part of it that
+    /// overlaps a copy of the lens disc shifted towards the frame's edge lets light through. This
+    /// squeezes out of focus highlights into the cat's eye shapes real lenses make near the corners.
+    fn defocus_disc_sample(self: &Camera, frame_x: f64, frame_y: f64, rng: &mut impl Rng) -> Option<Point3<f64>> {
+        let p = self.aperture.sample(rng);
+        // Without vignetting the whole aperture lets light through, even the corners of a mask
+        let barrel = self.optical_vignetting * Vector2::new(frame_x, frame_y);
+        if self.optical_vignetting > 0.0 && (p - barrel).magnitude2() > 1.0 {
+            return None;
+        }
+        Some(self.camera_center + (p.x * self.defocus_disc_u) + (p.y * self.defocus_disc_v))
     }
 }
 
+#[derive(Clone)]
 pub struct CameraConfig {
     pub aspect_ratio: f64,
     pub image_width: i32,
-    pub samples_per_pixel: i32,
+    pub samples_per_pixel: u32,
     pub max_depth: i32,
     pub vfov: f64,
     pub lookfrom: Point3<f64>,
     pub lookat: Point3<f64>,
     pub vup: Vector3<f64>,
     pub defocus_angle: f64,
-    pub focus_dist: f64
-}
\ No newline at end of file
+    pub focus_dist: f64,
+    pub projection: Projection,
+    pub stereo: Option<Stereo>,
+    /// Describes the camera the way a photographer would, replacing `vfov` and `defocus_angle`
+    pub physical: Option<PhysicalCamera>,
+    /// Brightens the image by this many stops, on top of a physical camera's exposure
+    pub exposure_compensation: f64,
+    pub tone_mapping: ToneMapping,
+    /// Color space light is added up and multiplied in
+    pub working_space: ColorSpace,
+    /// What the output image is encoded for
+    pub display: Display,
+    /// Final grade applied to the display encoded image
+    pub lut: Option<Arc<Lut>>,
+    pub aperture: Aperture,
+    /// How strongly the lens barrel clips the aperture towards the corners of the frame, from 0
+    /// (not at all) to 1 (down to a sliver)
+    pub optical_vignetting: f64,
+    pub tilt_shift: Option<TiltShift>,
+    /// Lens distortion applied to the rendered image, for the perspective projection
+    pub distortion: Option<Distortion>,
+    /// Render only this window of the image
+    pub crop: Option<Crop>,
+    /// Width and height of the square tiles the image is rendered in, in pixels
+    pub tile_size: i32,
+    pub tile_order: TileOrder,
+    /// Starting point of every random number stream used while rendering
+    pub seed: u64,
+    pub sampler: SamplerKind,
+    /// Stop sampling pixels once they look smooth, with `samples_per_pixel` as the most any takes
+    pub adaptive: Option<AdaptiveSampling>,
+    /// How samples are weighted into the pixels around them
+    pub filter: Filter,
+    /// Passes to render along with the beauty image
+    pub aovs: Vec<Aov>,
+    /// Path trace the scene, or show one of the debug views instead
+    pub mode: RenderMode
+}
+impl Default for CameraConfig {
+    /// A 1200 pixel wide 16:9 pinhole camera at the origin looking down -z, taking 500 samples
+    /// per pixel, with every other setting off.
+    fn default() -> Self {
+        CameraConfig {
+            aspect_ratio: 16.0 / 9.0,
+            image_width: 1200,
+            samples_per_pixel: 500,
+            max_depth: 50,
+            vfov: 90.0,
+            lookfrom: Point3::new(0.0, 0.0, 0.0),
+            lookat: Point3::new(0.0, 0.0, -1.0),
+            vup: Vector3::new(0.0, 1.0, 0.0),
+            defocus_angle: 0.0,
+            focus_dist: 10.0,
+            projection: Projection::Perspective,
+            stereo: None,
+            physical: None,
+            exposure_compensation: 0.0,
+            tone_mapping: ToneMapping::Clip,
+            working_space: ColorSpace::LinearSrgb,
+            display: Display::Srgb,
+            lut: None,
+            aperture: Aperture::Circular,
+            optical_vignetting: 0.0,
+            tilt_shift: None,
+            distortion: None,
+            crop: None,
+            tile_size: 32,
+            tile_order: TileOrder::Spiral,
+            seed: 0,
+            sampler: SamplerKind::Sobol,
+            adaptive: None,
+            filter: Filter { kind: FilterKind::Box, radius: 0.5 },
+            aovs: vec![],
+            mode: RenderMode::Beauty
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct AdaptiveSampling {
+    /// Samples every pixel takes before its noise is trusted
+    pub min_samples: u32,
+    /// Estimated error, in display units from 0 to 1 before tone mapping, below which a pixel is done
+    pub noise_threshold: f64
+}
+
+/// A rectangle of pixels, from `(x0, y0)` up to but not including `(x1, y1)`.
+#[derive(Clone, Copy)]
+pub struct Crop {
+    pub x0: i32,
+    pub y0: i32,
+    pub x1: i32,
+    pub y1: i32,
+    /// Output the whole image with black outside the window, instead of just the window
+    pub full_frame: bool
+}
+
+/// Camera movements of a view camera or tilt-shift lens.
+#[derive(Clone, Copy)]
+pub struct TiltShift {
+    /// Turn of the plane in focus about the horizontal axis, in degrees; positive angles push the
+    /// focus further away towards the top of the frame, to keep a receding floor sharp
+    pub tilt: f64,
+    /// Turn of the plane in focus about the vertical axis, in degrees; positive angles push the
+    /// focus further away towards the right of the frame
+    pub swing: f64,
+    /// Moves the framing to the right by this fraction of the image width
+    pub shift_x: f64,
+    /// Moves the framing up by this fraction of the image height, to keep verticals upright
+    pub shift_y: f64
+}
+
+/// A real camera body and lens. World units are taken to be meters.
+#[derive(Clone, Copy)]
+pub struct PhysicalCamera {
+    /// Focal length of the lens, in millimeters
+    pub focal_length: f64,
+    /// Height of the sensor, in millimeters (24 for full frame)
+    pub sensor_height: f64,
+    pub f_stop: f64,
+    /// Shutter speed, in seconds
+    pub shutter_speed: f64,
+    pub iso: f64,
+    /// Pixel whose subject the camera focuses on, replacing `focus_dist`
+    pub autofocus: Option<(i32, i32)>
+}
+impl PhysicalCamera {
+    /// Vertical field of view in degrees.
+    pub fn vfov(self: &PhysicalCamera) -> f64 {
+        (2.0 * f64::atan(self.sensor_height / (2.0 * self.focal_length))).to_degrees()
+    }
+    /// The cone angle, in degrees, that the aperture subtends from a point `focus_dist` away.
+    pub fn defocus_angle(self: &PhysicalCamera, focus_dist: f64) -> f64 {
+        let aperture_radius = self.focal_length / self.f_stop / 2.0 / 1000.0;
+        (2.0 * f64::atan(aperture_radius / focus_dist)).to_degrees()
+    }
+    /// How much light reaches the sensor relative to the "sunny 16" exposure (f/16, 1/100s,
+    /// ISO 100), which suits the default sky.
+    pub fn exposure(self: &PhysicalCamera) -> f64 {
+        let sunny_16 = (1.0 / 100.0) / (16.0 * 16.0);
+        self.shutter_speed * (self.iso / 100.0) / (self.f_stop * self.f_stop) / sunny_16
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Stereo {
+    pub layout: StereoLayout,
+    /// Distance between the two eyes, in world units
+    pub interocular_distance: f64,
+    /// Distance at which the eyes' images line up, or the focus distance if `None`
+    pub convergence_distance: Option<f64>
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum StereoLayout {
+    /// The left eye's image on the left, the right eye's on the right
+    SideBySide,
+    /// The left eye's image on top, the right eye's below
+    TopBottom
+}
+
+#[derive(Clone, Copy)]
+pub enum Projection {
+    /// A pinhole or thin lens camera seeing `vfov` degrees from top to bottom
+    Perspective,
+    /// Parallel rays covering `view_width` world units from left to right
+    Orthographic { view_width: f64 },
+    /// A circular fisheye lens seeing `fov` degrees from the top edge to the bottom edge
+    Fisheye { fov: f64, mapping: FisheyeMapping },
+    /// A full 360 by 180 degree latitude-longitude panorama around the camera
+    Equirectangular
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum FisheyeMapping {
+    /// Distance from the image center grows linearly with the angle off axis
+    Equidistant,
+    /// Equal areas of the image cover equal solid angles
+    Equisolid
+}
+
+/// The plate's color at the continuous pixel position `(x, y)`, where pixel centers sit on whole
+/// numbers. Positions outside the plate are black.
+fn sample_bilinear(plate: &Rgb32FImage, x: f64, y: f64) -> Rgb<f32> {
+    let (width, height) = plate.dimensions();
+    if x < -0.5 || y < -0.5 || x > width as f64 - 0.5 || y > height as f64 - 0.5 {
+        return Rgb([0.0, 0.0, 0.0]);
+    }
+    let x = x.clamp(0.0, (width - 1) as f64);
+    let y = y.clamp(0.0, (height - 1) as f64);
+    let (x0, y0) = (x.floor() as u32, y.floor() as u32);
+    let (x1, y1) = ((x0 + 1).min(width - 1), (y0 + 1).min(height - 1));
+    let (fx, fy) = ((x - x0 as f64) as f32, (y - y0 as f64) as f32);
+    let mut color = [0.0; 3];
+    for (c, value) in color.iter_mut().enumerate() {
+        let top = plate.get_pixel(x0, y0).0[c] * (1.0 - fx) + plate.get_pixel(x1, y0).0[c] * fx;
+        let bottom = plate.get_pixel(x0, y1).0[c] * (1.0 - fx) + plate.get_pixel(x1, y1).0[c] * fx;
+        *value = top * (1.0 - fy) + bottom * fy;
+    }
+    Rgb(color)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn open_mask_without_vignetting_loses_no_samples() {
+        let path = std::env::temp_dir().join(format!("open-mask-{}.png", std::process::id()));
+        image::GrayImage::from_pixel(8, 8, image::Luma([255])).save(&path).unwrap();
+        let aperture = Aperture::from_image(&path);
+        std::fs::remove_file(&path).unwrap();
+        let camera = Camera::initialize(CameraConfig {
+            image_width: 64,
+            defocus_angle: 2.0,
+            aperture: aperture.unwrap(),
+            ..Default::default()
+        });
+        let mut rng = StdRng::seed_from_u64(1);
+        for (x, y) in [(0.0, 0.0), (1.0, 1.0), (-1.0, 0.5)] {
+            for _ in 0..1000 {
+                assert!(camera.defocus_disc_sample(x, y, &mut rng).is_some());
+            }
+        }
+    }
+}
@@ -0,0 +1,184 @@
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Read, Write};
+use std::path::Path;
+use cgmath::Vector3;
+use clap::ValueEnum;
+
+use Vector3 as Color3;
+use crate::aov::Aov;
+use crate::film::{Film, FilmPixel};
+
+const MAGIC: &[u8; 8] = b"RTCKPT04";
+
+/// The state of a render in progress: the seed, which together with each pixel's sample count
+/// says where every random number stream continues from, a hash of the settings the render was
+/// started with, and the films rendered so far (one per eye).
+///
+/// Stored as the magic bytes `RTCKPT04`, the seed, the settings hash, the number of films and then
+/// for each film its width, height, passes and pixels, each pixel followed by the totals of its
+/// passes, all little-endian.
+pub struct Checkpoint {
+    pub seed: u64,
+    pub settings: u64,
+    pub films: Vec<Film>
+}
+impl Checkpoint {
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        let file = File::open(path)?;
+        let length = file.metadata()?.len();
+        let mut input = BufReader::new(file);
+        let mut magic = [0; 8];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "not a checkpoint file"));
+        }
+        let seed = read_u64(&mut input)?;
+        let settings = read_u64(&mut input)?;
+        let count = read_u32(&mut input)?;
+        let mut films = vec![];
+        for _ in 0..count {
+            let width = read_u32(&mut input)?;
+            let height = read_u32(&mut input)?;
+            let aov_count = read_u32(&mut input)?;
+            // A damaged file could claim any size, so check it holds that many pixels before
+            // making room for them
+            let pixel_size = 6 * 8 + 4 + aov_count as u64 * 3 * 8;
+            let size = (width as u64).checked_mul(height as u64).and_then(|pixels| pixels.checked_mul(pixel_size));
+            let too_large = width > i32::MAX as u32 || height > i32::MAX as u32 || size.is_none_or(|size| size > length);
+            if too_large || aov_count as usize > Aov::value_variants().len() {
+                return Err(Error::new(ErrorKind::InvalidData, "checkpoint is damaged"));
+            }
+            let aovs = (0..aov_count).map(|_| {
+                let index = read_u32(&mut input)? as usize;
+                Aov::value_variants().get(index).copied()
+                    .ok_or_else(|| Error::new(ErrorKind::InvalidData, "unknown render pass in checkpoint"))
+            }).collect::<Result<Vec<Aov>, Error>>()?;
+            let (width, height) = (width as i32, height as i32);
+            let mut film = Film::with_aovs(width, height, aovs);
+            for y in 0..height {
+                for x in 0..width {
+                    *film.get_mut(x, y) = FilmPixel {
+                        sum: read_color(&mut input)?,
+                        weight: read_f64(&mut input)?,
+                        luminance_sum: read_f64(&mut input)?,
+                        luminance_squares: read_f64(&mut input)?,
+                        samples: read_u32(&mut input)?
+                    };
+                    for sum in film.aov_sums_mut(x, y) {
+                        *sum = read_color(&mut input)?;
+                    }
+                }
+            }
+            films.push(film);
+        }
+        Ok(Checkpoint { seed, settings, films })
+    }
+    /// Writes a checkpoint next to `path` first and then moves it into place, so being stopped
+    /// halfway through never leaves a broken checkpoint behind.
+    pub fn save(path: &Path, seed: u64, settings: u64, films: &[Film]) -> Result<(), Error> {
+        let mut partial = path.as_os_str().to_owned();
+        partial.push(".partial");
+        let mut output = BufWriter::new(File::create(&partial)?);
+        output.write_all(MAGIC)?;
+        output.write_all(&seed.to_le_bytes())?;
+        output.write_all(&settings.to_le_bytes())?;
+        output.write_all(&(films.len() as u32).to_le_bytes())?;
+        for film in films {
+            output.write_all(&(film.width as u32).to_le_bytes())?;
+            output.write_all(&(film.height as u32).to_le_bytes())?;
+            output.write_all(&(film.aovs.len() as u32).to_le_bytes())?;
+            for aov in &film.aovs {
+                let index = Aov::value_variants().iter().position(|a| a == aov).unwrap_or_default();
+                output.write_all(&(index as u32).to_le_bytes())?;
+            }
+            for y in 0..film.height {
+                for x in 0..film.width {
+                    let pixel = film.get(x, y);
+                    for value in [pixel.sum.x, pixel.sum.y, pixel.sum.z, pixel.weight, pixel.luminance_sum, pixel.luminance_squares] {
+                        output.write_all(&value.to_le_bytes())?;
+                    }
+                    output.write_all(&pixel.samples.to_le_bytes())?;
+                    for sum in film.aov_sums(x, y) {
+                        for value in [sum.x, sum.y, sum.z] {
+                            output.write_all(&value.to_le_bytes())?;
+                        }
+                    }
+                }
+            }
+        }
+        output.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        fs::rename(partial, path)
+    }
+}
+
+fn read_u32(input: &mut impl Read) -> Result<u32, Error> {
+    let mut bytes = [0; 4];
+    input.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+fn read_u64(input: &mut impl Read) -> Result<u64, Error> {
+    let mut bytes = [0; 8];
+    input.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+fn read_f64(input: &mut impl Read) -> Result<f64, Error> {
+    Ok(f64::from_bits(read_u64(input)?))
+}
+fn read_color(input: &mut impl Read) -> Result<Color3<f64>, Error> {
+    Ok(Color3::new(read_f64(input)?, read_f64(input)?, read_f64(input)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn film(width: i32, height: i32, aovs: Vec<Aov>, offset: f64) -> Film {
+        let mut film = Film::with_aovs(width, height, aovs);
+        for y in 0..height {
+            for x in 0..width {
+                let v = offset + (y * width + x) as f64 / 7.0;
+                *film.get_mut(x, y) = FilmPixel {
+                    sum: Color3::new(v, 2.0 * v, -v),
+                    weight: v + 0.5,
+                    luminance_sum: v * v,
+                    luminance_squares: v * v * v,
+                    samples: (x + y) as u32
+                };
+                for (i, sum) in film.aov_sums_mut(x, y).iter_mut().enumerate() {
+                    *sum = Color3::new(v, i as f64, 1.0 / (v + 1.0));
+                }
+            }
+        }
+        film
+    }
+
+    #[test]
+    fn open_reads_back_what_save_wrote() {
+        let path = std::env::temp_dir().join(format!("round-trip-{}.ckpt", std::process::id()));
+        let films = [film(5, 3, vec![Aov::Normal, Aov::ObjectId, Aov::Emission], 0.0), film(5, 3, vec![], 10.0)];
+        Checkpoint::save(&path, 42, 7, &films).unwrap();
+        let checkpoint = Checkpoint::open(&path);
+        fs::remove_file(&path).unwrap();
+        let checkpoint = checkpoint.unwrap();
+        assert_eq!((checkpoint.seed, checkpoint.settings, checkpoint.films.len()), (42, 7, 2));
+        for (read, written) in checkpoint.films.iter().zip(&films) {
+            assert_eq!((read.width, read.height), (written.width, written.height));
+            assert!(read.aovs == written.aovs);
+            assert_eq!(read.aov_sums, written.aov_sums);
+            for (a, b) in read.pixels.iter().zip(&written.pixels) {
+                assert_eq!((a.sum, a.weight, a.luminance_sum, a.luminance_squares, a.samples), (b.sum, b.weight, b.luminance_sum, b.luminance_squares, b.samples));
+            }
+        }
+    }
+
+    #[test]
+    fn open_rejects_truncated_files() {
+        let path = std::env::temp_dir().join(format!("truncated-{}.ckpt", std::process::id()));
+        Checkpoint::save(&path, 1, 2, &[film(4, 4, vec![Aov::Depth], 0.0)]).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        fs::write(&path, &bytes[..bytes.len() - 1]).unwrap();
+        let result = Checkpoint::open(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(result.is_err());
+    }
+}
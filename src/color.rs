@@ -0,0 +1,143 @@
+use cgmath::Vector3;
+use clap::ValueEnum;
+
+use Vector3 as Color3;
+
+/// The RGB space light is added up and multiplied in while rendering.
+///
+/// Scene colors are written down in linear Rec. 709 and converted as they are used, so the same
+/// scene renders in either space. Multiplying reflectances in a wider space like ACEScg follows
+/// real interreflections between saturated surfaces more closely.
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ColorSpace {
+    /// Linear sRGB, which has the same primaries as Rec. 709
+    LinearSrgb,
+    /// ACES AP1 primaries with a D60 white point, as used by ACES compositing pipelines
+    Acescg
+}
+impl ColorSpace {
+    /// `color`, given in linear Rec. 709, in this space.
+    pub fn from_linear_srgb(self: &ColorSpace, color: Color3<f64>) -> Color3<f64> {
+        match self {
+            ColorSpace::LinearSrgb => color,
+            ColorSpace::Acescg => transform(&REC709_TO_ACESCG, color)
+        }
+    }
+    /// `color`, given in this space, in linear Rec. 709.
+    pub fn to_linear_srgb(self: &ColorSpace, color: Color3<f64>) -> Color3<f64> {
+        match self {
+            ColorSpace::LinearSrgb => color,
+            ColorSpace::Acescg => transform(&ACESCG_TO_REC709, color)
+        }
+    }
+}
+
+/// The display an image is encoded for: its primaries and the transfer function that goes with
+/// them.
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Display {
+    /// sRGB primaries and transfer function
+    Srgb,
+    /// P3 primaries with a D65 white point and the sRGB transfer function
+    DisplayP3,
+    /// Rec. 2020 primaries with the BT.1886 gamma of 2.4
+    Rec2020
+}
+impl Display {
+    /// The encoded values in `[0, 1]` this display needs to show `color`, given in linear
+    /// Rec. 709. Colors outside the display's gamut are clipped.
+    pub fn encode(self: &Display, color: Color3<f64>) -> Color3<f64> {
+        let linear = match self {
+            Display::Srgb => color,
+            Display::DisplayP3 => transform(&REC709_TO_P3, color),
+            Display::Rec2020 => transform(&REC709_TO_REC2020, color)
+        }.map(|c| c.clamp(0.0, 1.0));
+        match self {
+            Display::Srgb | Display::DisplayP3 => linear.map(linear_to_srgb),
+            Display::Rec2020 => linear.map(|c| c.powf(1.0 / 2.4))
+        }
+    }
+}
+
+/// The sRGB transfer function: linear near black, then a 2.4 power curve.
+pub fn linear_to_srgb(i: f64) -> f64 {
+    if i <= 0.0031308 { 12.92 * i } else { 1.055 * i.powf(1.0 / 2.4) - 0.055 }
+}
+/// The inverse of `linear_to_srgb`, for reading 8-bit images.
+pub fn srgb_to_linear(i: f64) -> f64 {
+    if i <= 0.04045 { i / 12.92 } else { ((i + 0.055) / 1.055).powf(2.4) }
+}
+
+// Conversions between sets of primaries, row by row. Those to and from ACEScg adapt the white
+// point between D65 and D60 with the Bradford transform.
+const REC709_TO_ACESCG: [[f64; 3]; 3] = [
+    [0.613097402, 0.339523146, 0.047379451],
+    [0.070193722, 0.916353879, 0.013452398],
+    [0.020615593, 0.109569773, 0.869814634]
+];
+const ACESCG_TO_REC709: [[f64; 3]; 3] = [
+    [1.705050993, -0.621792121, -0.083258872],
+    [-0.130256417, 1.140804736, -0.010548319],
+    [-0.024003357, -0.128968976, 1.152972333]
+];
+const REC709_TO_P3: [[f64; 3]; 3] = [
+    [0.822461969, 0.177538031, 0.0],
+    [0.033194199, 0.966805801, 0.0],
+    [0.017082631, 0.072397440, 0.910519929]
+];
+const REC709_TO_REC2020: [[f64; 3]; 3] = [
+    [0.627403896, 0.329283039, 0.043313065],
+    [0.069097289, 0.919540395, 0.011362316],
+    [0.016391439, 0.088013308, 0.895595253]
+];
+
+/// `matrix` times `color`, with the matrix given row by row.
+pub fn transform(matrix: &[[f64; 3]; 3], color: Color3<f64>) -> Color3<f64> {
+    let row = |r: &[f64; 3]| r[0] * color.x + r[1] * color.y + r[2] * color.z;
+    Color3::new(row(&matrix[0]), row(&matrix[1]), row(&matrix[2]))
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::InnerSpace;
+    use super::*;
+
+    fn multiply(a: &[[f64; 3]; 3], b: &[[f64; 3]; 3]) -> [[f64; 3]; 3] {
+        let mut product = [[0.0; 3]; 3];
+        for (i, row) in product.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+            }
+        }
+        product
+    }
+
+    #[test]
+    fn acescg_matrices_are_inverses() {
+        for product in [multiply(&REC709_TO_ACESCG, &ACESCG_TO_REC709), multiply(&ACESCG_TO_REC709, &REC709_TO_ACESCG)] {
+            for (i, row) in product.iter().enumerate() {
+                for (j, value) in row.iter().enumerate() {
+                    let expected = if i == j { 1.0 } else { 0.0 };
+                    assert!((value - expected).abs() < 1e-6, "{:?}", product);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn white_stays_white() {
+        let white = Color3::new(1.0, 1.0, 1.0);
+        for matrix in [&REC709_TO_ACESCG, &ACESCG_TO_REC709, &REC709_TO_P3, &REC709_TO_REC2020] {
+            assert!((transform(matrix, white) - white).magnitude() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn srgb_transfer_functions_are_inverses() {
+        for i in 0..=1000 {
+            let x = i as f64 / 1000.0;
+            assert!((srgb_to_linear(linear_to_srgb(x)) - x).abs() < 1e-12);
+            assert!((linear_to_srgb(srgb_to_linear(x)) - x).abs() < 1e-12);
+        }
+    }
+}
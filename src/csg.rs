@@ -0,0 +1,258 @@
+use cgmath::{InnerSpace, Vector3};
+
+use Vector3 as Point3;
+use cgmath::Vector2;
+use crate::hittable::{sphere_uv, HitRecord, Hittable};
+use crate::ray::Ray;
+use crate::util::{ray_box_intersection, Interval, vector_length_squared};
+
+#[derive(Clone, Copy)]
+pub enum CsgOperation {
+    /// Inside either child
+    Union,
+    /// Inside both children
+    Intersection,
+    /// Inside the left child but not the right one
+    Difference
+}
+impl CsgOperation {
+    fn inside(self: &CsgOperation, left: bool, right: bool) -> bool {
+        match self {
+            CsgOperation::Union => left || right,
+            CsgOperation::Intersection => left && right,
+            CsgOperation::Difference => left && !right
+        }
+    }
+}
+
+/// A point where a ray crosses the surface of a solid.
+#[derive(Clone, Copy)]
+pub struct Boundary<'a> {
+    pub t: f64,
+    pub outward_normal: Vector3<f64>,
+    pub object: &'a Hittable
+}
+/// A stretch of a ray that lies inside a solid.
+pub struct Span<'a> {
+    pub enter: Boundary<'a>,
+    pub exit: Boundary<'a>
+}
+
+impl Hittable {
+    /// Every interval, in increasing order of `t`, along which `ray` is inside this solid.
+    ///
+    /// Intervals cover the whole line, including negative `t`, so that a ray starting inside a
+    /// solid still knows it is inside. Surfaces that do not enclose a volume have no spans. Every
+    /// primitive tested adds one to `cost`.
+    pub fn spans(self: &Hittable, ray: &Ray, cost: &mut u64) -> Vec<Span<'_>> {
+        if !matches!(self, Hittable::Csg { .. }) {
+            *cost += 1;
+        }
+        match self {
+            Hittable::Circle { center, radius, .. } => {
+                let oc = ray.origin - center;
+                let a = vector_length_squared(ray.direction);
+                let half_b = oc.dot(ray.direction);
+                let c = vector_length_squared(oc) - radius * radius;
+                let discriminant = half_b * half_b - a * c;
+                if discriminant <= 0.0 {
+                    return vec![];
+                }
+                let sqrtd = f64::sqrt(discriminant);
+                let boundary = |t: f64| Boundary {
+                    t,
+                    outward_normal: (ray.at(t) - center) / *radius,
+                    object: self
+                };
+                vec![Span { enter: boundary((-half_b - sqrtd) / a), exit: boundary((-half_b + sqrtd) / a) }]
+            }
+            Hittable::Cuboid { min, max, .. } => {
+                match ray_box_intersection(ray, *min, *max) {
+                    Some((t_enter, t_exit)) => {
+                        let boundary = |t: f64| Boundary { t, outward_normal: cuboid_normal(ray.at(t), *min, *max), object: self };
+                        vec![Span { enter: boundary(t_enter), exit: boundary(t_exit) }]
+                    }
+                    None => vec![]
+                }
+            }
+            Hittable::Cylinder { base, top, radius, .. } => {
+                let axis = top - base;
+                let height = axis.magnitude();
+                let a_hat = axis / height;
+                let oc = ray.origin - base;
+
+                // Between the two caps
+                let s0 = oc.dot(a_hat);
+                let ds = ray.direction.dot(a_hat);
+                let (mut enter, mut exit) = if ds.abs() < 1e-12 {
+                    if s0 < 0.0 || s0 > height { return vec![] }
+                    (None, None)
+                } else {
+                    let t_base = Boundary { t: -s0 / ds, outward_normal: -a_hat, object: self };
+                    let t_top = Boundary { t: (height - s0) / ds, outward_normal: a_hat, object: self };
+                    if ds > 0.0 { (Some(t_base), Some(t_top)) } else { (Some(t_top), Some(t_base)) }
+                };
+
+                // Inside the infinite tube
+                let d_perp = ray.direction - ds * a_hat;
+                let oc_perp = oc - s0 * a_hat;
+                let a = vector_length_squared(d_perp);
+                let half_b = d_perp.dot(oc_perp);
+                let c = vector_length_squared(oc_perp) - radius * radius;
+                if a < 1e-12 {
+                    if c > 0.0 { return vec![] }
+                } else {
+                    let discriminant = half_b * half_b - a * c;
+                    if discriminant <= 0.0 {
+                        return vec![];
+                    }
+                    let sqrtd = f64::sqrt(discriminant);
+                    let tube = |t: f64| {
+                        let p = ray.at(t) - base;
+                        Boundary { t, outward_normal: (p - p.dot(a_hat) * a_hat) / *radius, object: self }
+                    };
+                    let tube_enter = tube((-half_b - sqrtd) / a);
+                    let tube_exit = tube((-half_b + sqrtd) / a);
+                    if enter.is_none_or(|b| tube_enter.t > b.t) { enter = Some(tube_enter) }
+                    if exit.is_none_or(|b| tube_exit.t < b.t) { exit = Some(tube_exit) }
+                }
+                match (enter, exit) {
+                    (Some(enter), Some(exit)) if enter.t < exit.t => vec![Span { enter, exit }],
+                    _ => vec![]
+                }
+            }
+            Hittable::Csg { operation, left, right } => {
+                combine(*operation, left.spans(ray, cost), right.spans(ray, cost))
+            }
+            _ => vec![]
+        }
+    }
+    /// The nearest crossing of this solid's surface within `ray_t`.
+    pub fn solid_hit(self: &Hittable, ray: &Ray, ray_t: Interval, cost: &mut u64) -> Option<HitRecord> {
+        let boundary = self.spans(ray, cost)
+            .into_iter()
+            .flat_map(|span| [span.enter, span.exit])
+            .find(|boundary| ray_t.surrounds(boundary.t))?;
+        let material = match boundary.object {
+            Hittable::Circle { material, .. }
+            | Hittable::Cuboid { material, .. }
+            | Hittable::Cylinder { material, .. } => material.clone(),
+            _ => unreachable!("only primitive solids bound a span")
+        };
+        let uv = match boundary.object {
+            Hittable::Circle { .. } => sphere_uv(boundary.outward_normal),
+            _ => Vector2::new(0.0, 0.0)
+        };
+        let mut record = HitRecord {
+            point: ray.at(boundary.t),
+            normal: boundary.outward_normal,
+            t: boundary.t,
+            material,
+            front_face: true,
+            uv,
+            object: boundary.object.clone()
+        };
+        record.set_face_normal(ray, boundary.outward_normal);
+        Some(record)
+    }
+}
+
+/// Merges the spans of two solids by sweeping over their boundaries in order.
+fn combine<'a>(operation: CsgOperation, left: Vec<Span<'a>>, right: Vec<Span<'a>>) -> Vec<Span<'a>> {
+    // (boundary, belongs to the left child, is an entry)
+    let mut events = Vec::with_capacity(2 * (left.len() + right.len()));
+    for (spans, is_left) in [(left, true), (right, false)] {
+        for span in spans {
+            events.push((span.enter, is_left, true));
+            events.push((span.exit, is_left, false));
+        }
+    }
+    events.sort_by(|a, b| a.0.t.total_cmp(&b.0.t));
+
+    let mut spans = vec![];
+    let (mut in_left, mut in_right) = (false, false);
+    let mut enter: Option<Boundary> = None;
+    for (boundary, is_left, is_entry) in events {
+        let was_inside = operation.inside(in_left, in_right);
+        if is_left { in_left = is_entry } else { in_right = is_entry }
+        let inside = operation.inside(in_left, in_right);
+        if was_inside == inside {
+            continue;
+        }
+        // Leaving a child can mean entering the result (and vice versa), which turns the surface inside out
+        let oriented = if is_entry == inside {
+            boundary
+        } else {
+            Boundary { outward_normal: -boundary.outward_normal, ..boundary }
+        };
+        if inside {
+            enter = Some(oriented);
+        } else if let Some(enter) = enter.take() {
+            spans.push(Span { enter, exit: oriented });
+        }
+    }
+    spans
+}
+
+fn cuboid_normal(p: Point3<f64>, min: Point3<f64>, max: Point3<f64>) -> Vector3<f64> {
+    let center = (min + max) / 2.0;
+    let half = (max - min) / 2.0;
+    let local = p - center;
+    // The face whose plane the point lies closest to
+    let mut axis = 0;
+    let mut closest = f64::INFINITY;
+    for i in 0..3 {
+        let distance = (half[i] - local[i].abs()).abs();
+        if distance < closest {
+            closest = distance;
+            axis = i;
+        }
+    }
+    let mut normal = Vector3::new(0.0, 0.0, 0.0);
+    normal[axis] = local[axis].signum();
+    normal
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Material;
+
+    fn spans_of<'a>(object: &'a Hittable, intervals: &[(f64, f64)]) -> Vec<Span<'a>> {
+        let boundary = |t: f64, outward: f64| Boundary { t, outward_normal: Vector3::new(outward, 0.0, 0.0), object };
+        intervals.iter().map(|&(enter, exit)| Span { enter: boundary(enter, -1.0), exit: boundary(exit, 1.0) }).collect()
+    }
+    fn intervals(spans: &[Span]) -> Vec<(f64, f64)> {
+        spans.iter().map(|span| (span.enter.t, span.exit.t)).collect()
+    }
+
+    #[test]
+    fn combine_overlapping_spans() {
+        let solid = Hittable::Circle {
+            center: Point3::new(0.0, 0.0, 0.0),
+            radius: 1.0,
+            material: Material::Lambertian { albedo: Vector3::new(0.5, 0.5, 0.5) }
+        };
+        let left = || spans_of(&solid, &[(0.0, 2.0), (4.0, 5.0)]);
+        let right = || spans_of(&solid, &[(1.0, 3.0)]);
+        assert_eq!(intervals(&combine(CsgOperation::Union, left(), right())), [(0.0, 3.0), (4.0, 5.0)]);
+        assert_eq!(intervals(&combine(CsgOperation::Intersection, left(), right())), [(1.0, 2.0)]);
+        assert_eq!(intervals(&combine(CsgOperation::Difference, left(), right())), [(0.0, 1.0), (4.0, 5.0)]);
+    }
+
+    #[test]
+    fn difference_turns_the_cut_surface_inside_out() {
+        let solid = Hittable::Circle {
+            center: Point3::new(0.0, 0.0, 0.0),
+            radius: 1.0,
+            material: Material::Lambertian { albedo: Vector3::new(0.5, 0.5, 0.5) }
+        };
+        let spans = combine(CsgOperation::Difference, spans_of(&solid, &[(0.0, 2.0)]), spans_of(&solid, &[(1.0, 3.0)]));
+        // Leaving the plate where the drill enters it faces along the ray, like any exit
+        assert_eq!(spans[0].exit.outward_normal, Vector3::new(1.0, 0.0, 0.0));
+        let spans = combine(CsgOperation::Difference, spans_of(&solid, &[(0.0, 3.0)]), spans_of(&solid, &[(1.0, 2.0)]));
+        assert_eq!(intervals(&spans), [(0.0, 1.0), (2.0, 3.0)]);
+        // Coming out of the drilled hole enters the plate, through the hole's wall turned around
+        assert_eq!(spans[1].enter.outward_normal, Vector3::new(-1.0, 0.0, 0.0));
+    }
+}
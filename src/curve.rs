@@ -0,0 +1,130 @@
+use cgmath::{InnerSpace, Vector3};
+
+use Vector3 as Point3;
+use crate::ray::Ray;
+use crate::util::{unit_vector, Interval};
+
+#[derive(Clone, Copy)]
+pub enum CurveShape {
+    /// A flat strip that always faces the incoming ray
+    Ribbon,
+    /// A flat strip shaded as if it were a round tube, for hair and fur
+    Cylinder
+}
+
+/// Where a ray crossed a curve: the distance along the ray, the position `u` along the curve and
+/// the position `v` across its width, both in `[0, 1]`.
+pub struct CurveHit {
+    pub t: f64,
+    pub u: f64,
+    pub v: f64
+}
+
+pub fn bezier(points: &[Point3<f64>; 4], u: f64) -> Point3<f64> {
+    let s = 1.0 - u;
+    s * s * s * points[0] + 3.0 * s * s * u * points[1] + 3.0 * s * u * u * points[2] + u * u * u * points[3]
+}
+pub fn bezier_tangent(points: &[Point3<f64>; 4], u: f64) -> Vector3<f64> {
+    let s = 1.0 - u;
+    let tangent = 3.0 * s * s * (points[1] - points[0]) + 6.0 * s * u * (points[2] - points[1]) + 3.0 * u * u * (points[3] - points[2]);
+    // The derivative vanishes where control points coincide, so fall back to the chord
+    if tangent.magnitude2() < 1e-16 { points[3] - points[0] } else { tangent }
+}
+fn split(points: &[Point3<f64>; 4]) -> ([Point3<f64>; 4], [Point3<f64>; 4]) {
+    let [p0, p1, p2, p3] = *points;
+    let p01 = (p0 + p1) / 2.0;
+    let p12 = (p1 + p2) / 2.0;
+    let p23 = (p2 + p3) / 2.0;
+    let p012 = (p01 + p12) / 2.0;
+    let p123 = (p12 + p23) / 2.0;
+    let mid = (p012 + p123) / 2.0;
+    ([p0, p01, p012, mid], [mid, p123, p23, p3])
+}
+
+/// Intersects a cubic Bézier curve whose width varies linearly from `widths.0` to `widths.1`.
+///
+/// The control points are moved into a space where the ray starts at the origin and runs down +z,
+/// then the curve is split in half until each piece is close to a straight line, which is tested
+/// by its distance to the z axis. Every piece tested adds one to `cost`.
+pub fn intersect_curve(ray: &Ray, points: &[Point3<f64>; 4], widths: (f64, f64), ray_t: &Interval, cost: &mut u64) -> Option<CurveHit> {
+    let ray_length = ray.direction.magnitude();
+    let z_axis = ray.direction / ray_length;
+    let helper = if z_axis.x.abs() > 0.9 { Vector3::new(0.0, 1.0, 0.0) } else { Vector3::new(1.0, 0.0, 0.0) };
+    let x_axis = unit_vector(helper.cross(z_axis));
+    let y_axis = z_axis.cross(x_axis);
+    let to_ray_space = |p: Point3<f64>| {
+        let d = p - ray.origin;
+        Point3::new(d.dot(x_axis), d.dot(y_axis), d.dot(z_axis))
+    };
+    let local = [to_ray_space(points[0]), to_ray_space(points[1]), to_ray_space(points[2]), to_ray_space(points[3])];
+
+    // Enough subdivisions for the pieces to be flat to within a twentieth of the curve's width
+    let mut flatness: f64 = 0.0;
+    for i in 0..2 {
+        let second_difference = local[i] - 2.0 * local[i + 1] + local[i + 2];
+        flatness = flatness.max(second_difference.x.abs().max(second_difference.y.abs()).max(second_difference.z.abs()));
+    }
+    let epsilon = f64::max(widths.0, widths.1) / 20.0;
+    let depth = if flatness > 0.0 {
+        (f64::log2(std::f64::consts::SQRT_2 * 6.0 * flatness / (8.0 * epsilon)) / 2.0).clamp(0.0, 10.0) as u32
+    } else {
+        0
+    };
+
+    let z_range = Interval::new(ray_t.min * ray_length, ray_t.max * ray_length);
+    recursive_intersect(&local, 0.0, 1.0, depth, widths, &z_range, cost).map(|hit| CurveHit { t: hit.t / ray_length, ..hit })
+}
+
+fn recursive_intersect(points: &[Point3<f64>; 4], u0: f64, u1: f64, depth: u32, widths: (f64, f64), z_range: &Interval, cost: &mut u64) -> Option<CurveHit> {
+    *cost += 1;
+    let max_width = lerp(u0, widths.0, widths.1).max(lerp(u1, widths.0, widths.1));
+    let half = max_width / 2.0;
+    let (mut min, mut max) = (points[0], points[0]);
+    for p in &points[1..] {
+        min = Point3::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z));
+        max = Point3::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z));
+    }
+    if min.x - half > 0.0 || max.x + half < 0.0 || min.y - half > 0.0 || max.y + half < 0.0
+        || max.z + half < z_range.min || min.z - half > z_range.max {
+        return None;
+    }
+
+    if depth > 0 {
+        let (first, second) = split(points);
+        let u_mid = (u0 + u1) / 2.0;
+        let first_hit = recursive_intersect(&first, u0, u_mid, depth - 1, widths, z_range, cost);
+        let nearer = match &first_hit {
+            Some(hit) => Interval::new(z_range.min, hit.t),
+            None => Interval::new(z_range.min, z_range.max)
+        };
+        return recursive_intersect(&second, u_mid, u1, depth - 1, widths, &nearer, cost).or(first_hit);
+    }
+
+    // Treat the piece as the segment between its end points and find the point nearest the ray
+    let start = points[0];
+    let end = points[3];
+    let segment = Vector3::new(end.x - start.x, end.y - start.y, 0.0);
+    let length_squared = segment.magnitude2();
+    let w = if length_squared > 0.0 {
+        ((-start.x * segment.x - start.y * segment.y) / length_squared).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    let u = lerp(w, u0, u1);
+    let width = lerp(u, widths.0, widths.1);
+    let nearest = bezier(points, w);
+    let distance_squared = nearest.x * nearest.x + nearest.y * nearest.y;
+    if distance_squared > width * width / 4.0 || !z_range.surrounds(nearest.z) {
+        return None;
+    }
+    // Which side of the curve the ray passed on decides whether v is above or below the middle
+    let tangent = bezier_tangent(points, w);
+    let distance = distance_squared.sqrt();
+    let side = tangent.x * -nearest.y + nearest.x * tangent.y;
+    let v = if side > 0.0 { 0.5 + distance / width } else { 0.5 - distance / width };
+    Some(CurveHit { t: nearest.z, u, v })
+}
+
+fn lerp(t: f64, a: f64, b: f64) -> f64 {
+    (1.0 - t) * a + t * b
+}
@@ -0,0 +1,141 @@
+use cgmath::{InnerSpace, Vector3};
+use clap::ValueEnum;
+use rand::Rng;
+
+use Vector3 as Color3;
+use crate::hittable::{HitRecord, Hittable, HittableList};
+use crate::film::heat;
+use crate::ray::Ray;
+use crate::util::{unit_vector, Interval};
+
+/// What the camera shows: the path traced image, or one of the views for finding problems with a
+/// scene, which shade the first surface each ray hits directly instead.
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum RenderMode {
+    /// The path traced image
+    Beauty,
+    /// Outward facing normals, with x, y and z as red, green and blue
+    Normals,
+    /// Distance to the first surface hit, nearer being brighter
+    Depth,
+    /// Surface coordinates, wrapped into [0, 1), as red and green
+    Uv,
+    /// The barycentric coordinates of triangle hits as red, green and blue
+    Barycentrics,
+    /// The surface color alone, without any lighting
+    Albedo,
+    /// Triangle edges drawn over a simply lit view
+    Wireframe,
+    /// Blue where rays hit the front of a surface, red where they hit the back
+    FaceOrientation,
+    /// Work done finding what each pixel's paths hit: primitives and bounding boxes tested, and
+    /// steps of sphere tracing, heightfield traversal and delta tracking. Shown from black through
+    /// red and yellow to white at the image's 99th percentile
+    Cost
+}
+
+/// The debug view `mode` of whatever `ray` hits. Every color is ready for the display, except
+/// albedo, which is linear, and depth and cost, which are left as raw numbers until the whole
+/// image can be scaled by `normalize`.
+///
+/// `pixel_size` gives how wide a pixel is at a distance from the camera, to draw wireframe lines
+/// about a pixel wide however far away they are.
+pub fn shade(
+    mode: RenderMode,
+    ray: &Ray,
+    hittables: &HittableList,
+    max_depth: i32,
+    pixel_size: impl Fn(f64) -> f64,
+    rng: &mut impl Rng
+) -> Color3<f64> {
+    let black = Color3::new(0.0, 0.0, 0.0);
+    if mode == RenderMode::Cost {
+        return Color3::new(path_cost(ray, hittables, max_depth, rng) as f64, 0.0, 0.0);
+    }
+    let Some(hit) = hittables.hit(ray, Interval::new(0.001, f64::INFINITY), rng) else {
+        return black;
+    };
+    let outward_normal = if hit.front_face { hit.normal } else { -hit.normal };
+    let distance = hit.t * ray.direction.magnitude();
+    // Lit from the eye, so shapes read without any lights
+    let eye_light = 0.2 + 0.8 * hit.normal.dot(-unit_vector(ray.direction)).abs();
+    match mode {
+        RenderMode::Beauty | RenderMode::Cost => black,
+        RenderMode::Normals => outward_normal * 0.5 + Color3::new(0.5, 0.5, 0.5),
+        // Depth is kept with how much of the pixel was hit, so edges against the sky are averaged
+        // towards black rather than towards the camera
+        RenderMode::Depth => Color3::new(distance, 1.0, 0.0),
+        RenderMode::Uv => Color3::new(hit.uv.x.rem_euclid(1.0), hit.uv.y.rem_euclid(1.0), 0.0),
+        RenderMode::Barycentrics => barycentrics(&hit).unwrap_or(Color3::new(0.2, 0.2, 0.2)),
+        RenderMode::Albedo => hit.material.scatter(ray, &hit, &hittables.textures, rng).0,
+        RenderMode::Wireframe => {
+            let on_edge = edge_distance(&hit).is_some_and(|edge| edge < 0.5 * pixel_size(distance));
+            if on_edge { Color3::new(1.0, 0.6, 0.0) } else { Color3::new(0.7, 0.7, 0.7) * eye_light }
+        }
+        RenderMode::FaceOrientation => {
+            let color = if hit.front_face { Color3::new(0.2, 0.3, 1.0) } else { Color3::new(1.0, 0.15, 0.15) };
+            color * eye_light
+        }
+    }
+}
+
+/// Pixel averages of a debug view made ready for the display: depth scaled so the farthest in
+/// the image is 1, cost scaled so its 99th percentile is and colored as a heatmap, and albedo
+/// encoded with `encode`. Other views are returned as they are.
+pub fn normalize(mode: RenderMode, values: &[Color3<f64>], encode: impl Fn(Color3<f64>) -> Color3<f64>) -> Vec<Color3<f64>> {
+    let depth = |value: &Color3<f64>| if value.y > 0.0 { value.x / value.y } else { 0.0 };
+    let max = match mode {
+        RenderMode::Depth => values.iter().map(depth).fold(0.0, f64::max),
+        // A few very costly pixels would leave the rest of the heatmap dark
+        RenderMode::Cost => {
+            let mut costs = values.iter().map(|value| value.x).collect::<Vec<f64>>();
+            costs.sort_by(f64::total_cmp);
+            costs.get(costs.len() * 99 / 100).copied().unwrap_or(0.0)
+        }
+        _ => 0.0
+    };
+    values.iter().map(|value| match mode {
+        RenderMode::Albedo => encode(*value),
+        // Nearer is brighter, and nothing hit is black
+        RenderMode::Depth => Color3::new(1.0, 1.0, 1.0) * (1.0 - depth(value) / max.max(f64::MIN_POSITIVE)) * value.y,
+        RenderMode::Cost => heat(value.x / max.max(1.0)),
+        _ => *value
+    }).collect()
+}
+
+/// The work of finding every hit along the path starting with `ray`, as counted by `Hittable::hit`.
+fn path_cost(ray: &Ray, hittables: &HittableList, max_depth: i32, rng: &mut impl Rng) -> u64 {
+    let mut cost = 0;
+    let mut ray = Ray { origin: ray.origin, direction: ray.direction, time: ray.time };
+    for _ in 0..max_depth.max(0) {
+        match hittables.hit_object(&ray, Interval::new(0.001, f64::INFINITY), rng, &mut cost) {
+            Some((_, hit)) => ray = hit.material.scatter(&ray, &hit, &hittables.textures, rng).1,
+            None => break
+        }
+    }
+    cost
+}
+
+/// Barycentric coordinates of a triangle hit, or `None` for other shapes.
+fn barycentrics(hit: &HitRecord) -> Option<Color3<f64>> {
+    let Hittable::Triangle { a, b, c, .. } = &hit.object else {
+        return None;
+    };
+    let normal = (b - a).cross(c - a);
+    let area = normal.magnitude2();
+    // Each vertex's weight is the area of the triangle opposite it, relative to the whole
+    let weight = |from: Vector3<f64>, to: Vector3<f64>| (to - from).cross(hit.point - from).dot(normal) / area;
+    Some(Color3::new(weight(*b, *c), weight(*c, *a), weight(*a, *b)).map(|w| w.clamp(0.0, 1.0)))
+}
+
+/// Distance from a triangle hit to the triangle's nearest edge, or `None` for other shapes.
+fn edge_distance(hit: &HitRecord) -> Option<f64> {
+    let Hittable::Triangle { a, b, c, .. } = &hit.object else {
+        return None;
+    };
+    let to_edge = |from: Vector3<f64>, to: Vector3<f64>| {
+        let edge = to - from;
+        (hit.point - from).cross(edge).magnitude() / edge.magnitude()
+    };
+    Some(to_edge(*a, *b).min(to_edge(*b, *c)).min(to_edge(*c, *a)))
+}
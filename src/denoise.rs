@@ -0,0 +1,113 @@
+use cgmath::{ElementWise, InnerSpace, Vector3};
+use rayon::prelude::*;
+
+use Vector3 as Color3;
+use crate::aov::Aov;
+use crate::film::{luminance, Film};
+
+/// The passes the denoiser is guided by, which a film must have for it to work.
+pub const GUIDES: [Aov; 3] = [Aov::Albedo, Aov::Normal, Aov::Depth];
+
+/// Weights of the 5-tap B3 spline the wavelet filter is built from.
+const KERNEL: [f64; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+const ITERATIONS: u32 = 5;
+/// How sharply each guide stops the blur at edges
+const SIGMA_LUMINANCE: f64 = 2.0;
+const NORMAL_POWER: i32 = 128;
+const SIGMA_DEPTH: f64 = 0.05;
+
+/// Smooths out the noise of a low sample render, in place, with an edge-avoiding à-trous wavelet
+/// filter (Dammertz et al., 2010, with the variance guided luminance weights of Schied et al.'s
+/// SVGF).
+///
+/// The albedo is divided out first so only the lighting is blurred and textures stay sharp. Each
+/// pass blurs with the 5x5 B3 spline, spread twice as far as the pass before, but weighs every
+/// neighbour by how alike its normal and depth are, and by how far its brightness is from the
+/// pixel's own compared to the noise the pixel's samples show. Films without the `GUIDES` passes
+/// are left as they are.
+pub fn denoise(film: &mut Film) {
+    let index = |aov: Aov| film.aovs.iter().position(|&a| a == aov);
+    let (Some(albedo_index), Some(normal_index), Some(depth_index)) = (index(Aov::Albedo), index(Aov::Normal), index(Aov::Depth)) else {
+        return;
+    };
+    let (width, height) = (film.width, film.height);
+    let pixels = (0..height).flat_map(|y| (0..width).map(move |x| (x, y))).collect::<Vec<(i32, i32)>>();
+    // Where nothing was hit there is no albedo to divide out
+    let albedo = pixels.iter().map(|&(x, y)| {
+        film.aov(x, y, albedo_index).map(|c| if c > 1e-3 { c } else { 1.0 })
+    }).collect::<Vec<Color3<f64>>>();
+    let normals = pixels.iter().map(|&(x, y)| {
+        // An average of opposing normals is too short to point anywhere, so count it as a miss
+        let normal = film.aov(x, y, normal_index);
+        if normal.magnitude2() > 1e-6 { normal.normalize() } else { Vector3::new(0.0, 0.0, 0.0) }
+    }).collect::<Vec<Vector3<f64>>>();
+    let depths = pixels.iter().map(|&(x, y)| film.aov(x, y, depth_index).x).collect::<Vec<f64>>();
+    let mut colors = pixels.iter().zip(&albedo).map(|(&(x, y), a)| film.get(x, y).mean().div_element_wise(*a)).collect::<Vec<_>>();
+    // Variance of each pixel's mean luminance, with the albedo divided out as well
+    let mut variances = pixels.iter().zip(&albedo).map(|(&(x, y), a)| {
+        let pixel = film.get(x, y);
+        if pixel.samples < 2 {
+            return 0.0;
+        }
+        let n = pixel.samples as f64;
+        let mean = pixel.luminance_sum / n;
+        let variance = f64::max(0.0, (pixel.luminance_squares - n * mean * mean) / (n - 1.0)) / n;
+        variance / luminance(*a).max(1e-3).powi(2)
+    }).collect::<Vec<f64>>();
+
+    let at = |x: i32, y: i32| (y * width + x) as usize;
+    for iteration in 0..ITERATIONS {
+        let step = 1 << iteration;
+        // The luminance weights use a slightly blurred variance, since one pixel's is noisy too
+        let blurred = pixels.par_iter().map(|&(x, y)| {
+            let (mut sum, mut weights) = (0.0, 0.0);
+            for dy in -1..=1 {
+                for dx in -1..=1 {
+                    let (qx, qy) = (x + dx, y + dy);
+                    if qx >= 0 && qx < width && qy >= 0 && qy < height {
+                        let w = KERNEL[(dx + 2) as usize] * KERNEL[(dy + 2) as usize];
+                        sum += w * variances[at(qx, qy)];
+                        weights += w;
+                    }
+                }
+            }
+            sum / weights
+        }).collect::<Vec<f64>>();
+        let filtered = pixels.par_iter().map(|&(x, y)| {
+            let p = at(x, y);
+            let luminance_p = luminance(colors[p]);
+            let luminance_scale = SIGMA_LUMINANCE * blurred[p].sqrt() + 1e-6;
+            let (mut color, mut variance, mut weights) = (Color3::new(0.0, 0.0, 0.0), 0.0, 0.0);
+            for dy in -2..=2 {
+                for dx in -2..=2 {
+                    let (qx, qy) = (x + dx * step, y + dy * step);
+                    if qx < 0 || qx >= width || qy < 0 || qy >= height {
+                        continue;
+                    }
+                    let q = at(qx, qy);
+                    let normal_weight = match (normals[p].magnitude2() > 0.0, normals[q].magnitude2() > 0.0) {
+                        (true, true) => normals[p].dot(normals[q]).max(0.0).powi(NORMAL_POWER),
+                        (false, false) => 1.0,
+                        _ => 0.0
+                    };
+                    let depth_weight = f64::exp(-(depths[p] - depths[q]).abs() / (SIGMA_DEPTH * depths[p].max(1e-3) * step as f64));
+                    let luminance_weight = f64::exp(-(luminance_p - luminance(colors[q])).abs() / luminance_scale);
+                    let w = KERNEL[(dx + 2) as usize] * KERNEL[(dy + 2) as usize] * normal_weight * depth_weight * luminance_weight;
+                    color += w * colors[q];
+                    variance += w * w * variances[q];
+                    weights += w;
+                }
+            }
+            // The pixel always counts towards itself, so `weights` is never 0
+            (color / weights, variance / (weights * weights))
+        }).collect::<Vec<_>>();
+        (colors, variances) = filtered.into_iter().unzip();
+    }
+
+    for (&(x, y), (color, a)) in pixels.iter().zip(colors.iter().zip(&albedo)) {
+        let pixel = film.get_mut(x, y);
+        if pixel.weight > 0.0 {
+            pixel.sum = color.mul_element_wise(*a) * pixel.weight;
+        }
+    }
+}
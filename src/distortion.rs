@@ -0,0 +1,60 @@
+/// Brown-Conrady lens distortion, with the coefficients used by common calibration tools.
+///
+/// Works on normalized image coordinates: positions on the image plane divided by the focal
+/// length, with the optical axis at the origin, x to the right and y up.
+#[derive(Clone, Copy)]
+pub struct Distortion {
+    /// Radial coefficients; negative `k1` bows straight lines outwards (barrel), positive inwards (pincushion)
+    pub k1: f64,
+    pub k2: f64,
+    pub k3: f64,
+    /// Tangential coefficients, for a lens that is not quite parallel to the sensor
+    pub p1: f64,
+    pub p2: f64
+}
+impl Distortion {
+    /// Where the lens images a point that an ideal pinhole camera would image at `(x, y)`.
+    pub fn distort(self: &Distortion, x: f64, y: f64) -> (f64, f64) {
+        let r2 = x * x + y * y;
+        let radial = 1.0 + r2 * (self.k1 + r2 * (self.k2 + r2 * self.k3));
+        let dx = 2.0 * self.p1 * x * y + self.p2 * (r2 + 2.0 * x * x);
+        let dy = self.p1 * (r2 + 2.0 * y * y) + 2.0 * self.p2 * x * y;
+        (x * radial + dx, y * radial + dy)
+    }
+    /// The inverse of `distort`: where an ideal pinhole camera would image the point that the
+    /// lens images at `(x, y)`.
+    ///
+    /// There is no closed form, so this starts from the distorted point and repeatedly corrects it
+    /// by the distortion found there, which converges for any lens that doesn't fold the image over.
+    pub fn undistort(self: &Distortion, x: f64, y: f64) -> (f64, f64) {
+        let (mut ux, mut uy) = (x, y);
+        for _ in 0..20 {
+            let r2 = ux * ux + uy * uy;
+            let radial = 1.0 + r2 * (self.k1 + r2 * (self.k2 + r2 * self.k3));
+            let dx = 2.0 * self.p1 * ux * uy + self.p2 * (r2 + 2.0 * ux * ux);
+            let dy = self.p1 * (r2 + 2.0 * uy * uy) + 2.0 * self.p2 * ux * uy;
+            (ux, uy) = ((x - dx) / radial, (y - dy) / radial);
+        }
+        (ux, uy)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn undistort_inverts_distort() {
+        let lenses = [
+            Distortion { k1: -0.2, k2: 0.05, k3: 0.0, p1: 0.0, p2: 0.0 },
+            Distortion { k1: 0.1, k2: -0.02, k3: 0.01, p1: 0.001, p2: -0.002 }
+        ];
+        for lens in lenses {
+            for (x, y) in [(0.0, 0.0), (0.3, -0.2), (-0.5, 0.4), (0.6, 0.6)] {
+                let (dx, dy) = lens.distort(x, y);
+                let (ux, uy) = lens.undistort(dx, dy);
+                assert!((ux - x).abs() < 1e-9 && (uy - y).abs() < 1e-9, "({x}, {y}) came back as ({ux}, {uy})");
+            }
+        }
+    }
+}
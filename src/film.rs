@@ -0,0 +1,151 @@
+use cgmath::Vector3;
+use image::{Rgb, RgbImage};
+
+use Vector3 as Color3;
+use crate::aov::Aov;
+
+/// Running totals of one pixel: its share of every sample splatted into it, and the samples
+/// taken in it.
+#[derive(Clone, Copy)]
+pub struct FilmPixel {
+    /// Filter weighted sum of the samples splatted into the pixel, and the sum of their weights
+    pub sum: Color3<f64>,
+    pub weight: f64,
+    /// Sums of the luminance of the pixel's own samples and its square, to estimate how noisy the
+    /// pixel still is
+    pub luminance_sum: f64,
+    pub luminance_squares: f64,
+    pub samples: u32
+}
+impl FilmPixel {
+    pub const EMPTY: FilmPixel = FilmPixel {
+        sum: Color3::new(0.0, 0.0, 0.0),
+        weight: 0.0,
+        luminance_sum: 0.0,
+        luminance_squares: 0.0,
+        samples: 0
+    };
+    /// Counts a sample taken in this pixel. Its color reaches `sum` by being splatted.
+    pub fn add(self: &mut FilmPixel, color: Color3<f64>) {
+        let luminance = luminance(color);
+        self.luminance_sum += luminance;
+        self.luminance_squares += luminance * luminance;
+        self.samples += 1;
+    }
+    /// Filter weighted average of the samples, or black if there are none.
+    pub fn mean(self: &FilmPixel) -> Color3<f64> {
+        if self.weight <= 0.0 { Color3::new(0.0, 0.0, 0.0) } else { self.sum / self.weight }
+    }
+    /// Estimated error of the pixel once brightened by `exposure`, in `[0, 1]` display units.
+    ///
+    /// The standard error of the mean luminance, scaled by the slope of a gamma 2 curve at that
+    /// luminance, which is close to the sRGB curve, so dark pixels need to be about as smooth as
+    /// bright ones look. Tone mapping is left out, so this overestimates the error of pixels a
+    /// tone curve compresses.
+    pub fn error(self: &FilmPixel, exposure: f64) -> f64 {
+        if self.samples < 2 {
+            return f64::INFINITY;
+        }
+        let n = self.samples as f64;
+        let mean = self.luminance_sum / n;
+        let variance = f64::max(0.0, (self.luminance_squares - n * mean * mean) / (n - 1.0));
+        let (mean, standard_error) = (exposure * mean, exposure * f64::sqrt(variance / n));
+        standard_error / (2.0 * f64::sqrt(f64::max(mean, 1e-4)))
+    }
+}
+
+/// The pixels of a rendered image, row by row, and running totals of the passes in `aovs` for
+/// each of them.
+#[derive(Clone)]
+pub struct Film {
+    pub width: i32,
+    pub height: i32,
+    pub pixels: Vec<FilmPixel>,
+    pub aovs: Vec<Aov>,
+    /// The totals of each pixel's passes, one after the other in the order of `aovs`
+    pub aov_sums: Vec<Color3<f64>>
+}
+impl Film {
+    pub fn new(width: i32, height: i32) -> Self {
+        Film::with_aovs(width, height, vec![])
+    }
+    pub fn with_aovs(width: i32, height: i32, aovs: Vec<Aov>) -> Self {
+        let count = (width * height) as usize;
+        Film {
+            width,
+            height,
+            pixels: vec![FilmPixel::EMPTY; count],
+            aov_sums: vec![Color3::new(0.0, 0.0, 0.0); count * aovs.len()],
+            aovs
+        }
+    }
+    pub fn get(self: &Film, x: i32, y: i32) -> &FilmPixel {
+        &self.pixels[(y * self.width + x) as usize]
+    }
+    pub fn get_mut(self: &mut Film, x: i32, y: i32) -> &mut FilmPixel {
+        &mut self.pixels[(y * self.width + x) as usize]
+    }
+    /// Running totals of pixel `(x, y)`'s passes.
+    pub fn aov_sums(self: &Film, x: i32, y: i32) -> &[Color3<f64>] {
+        let start = (y * self.width + x) as usize * self.aovs.len();
+        &self.aov_sums[start..start + self.aovs.len()]
+    }
+    pub fn aov_sums_mut(self: &mut Film, x: i32, y: i32) -> &mut [Color3<f64>] {
+        let start = (y * self.width + x) as usize * self.aovs.len();
+        &mut self.aov_sums[start..start + self.aovs.len()]
+    }
+    /// The value of pass number `index` at pixel `(x, y)`.
+    pub fn aov(self: &Film, x: i32, y: i32, index: usize) -> Color3<f64> {
+        let sum = self.aov_sums(x, y)[index];
+        let samples = self.get(x, y).samples;
+        if self.aovs[index].is_id() || samples == 0 { sum } else { sum / samples as f64 }
+    }
+    /// Copies pixel `(x, y)` of `part`, passes and all, to `(to_x, to_y)`.
+    fn copy_pixel(self: &mut Film, to_x: i32, to_y: i32, part: &Film, x: i32, y: i32) {
+        *self.get_mut(to_x, to_y) = *part.get(x, y);
+        self.aov_sums_mut(to_x, to_y).copy_from_slice(part.aov_sums(x, y));
+    }
+    /// `left` and `right` next to each other in one film.
+    pub fn beside(left: &Film, right: &Film) -> Film {
+        let mut film = Film::with_aovs(left.width + right.width, left.height.max(right.height), left.aovs.clone());
+        for (offset, part) in [(0, left), (left.width, right)] {
+            for y in 0..part.height {
+                for x in 0..part.width {
+                    film.copy_pixel(offset + x, y, part, x, y);
+                }
+            }
+        }
+        film
+    }
+    /// `top` above `bottom` in one film.
+    pub fn above(top: &Film, bottom: &Film) -> Film {
+        let mut film = Film::with_aovs(top.width.max(bottom.width), top.height + bottom.height, top.aovs.clone());
+        for (offset, part) in [(0, top), (top.height, bottom)] {
+            for y in 0..part.height {
+                for x in 0..part.width {
+                    film.copy_pixel(x, offset + y, part, x, y);
+                }
+            }
+        }
+        film
+    }
+    /// How many samples each pixel took, from black (none) through red and yellow to white
+    /// (`max_samples`).
+    pub fn heatmap(self: &Film, max_samples: u32) -> RgbImage {
+        RgbImage::from_fn(self.width as u32, self.height as u32, |x, y| {
+            let color = heat(self.get(x as i32, y as i32).samples as f64 / max_samples.max(1) as f64);
+            Rgb([(255.0 * color.x) as u8, (255.0 * color.y) as u8, (255.0 * color.z) as u8])
+        })
+    }
+}
+
+/// A heatmap color for `t` from 0 to 1: black through red and yellow to white.
+pub fn heat(t: f64) -> Color3<f64> {
+    let channel = |start: f64| ((t - start) * 3.0).clamp(0.0, 1.0);
+    Color3::new(channel(0.0), channel(1.0 / 3.0), channel(2.0 / 3.0))
+}
+
+/// Rec. 709 luminance of a linear color.
+pub fn luminance(color: Color3<f64>) -> f64 {
+    0.2126 * color.x + 0.7152 * color.y + 0.0722 * color.z
+}
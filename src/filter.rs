@@ -0,0 +1,132 @@
+use std::f64::consts::PI;
+use cgmath::Vector3;
+use clap::ValueEnum;
+
+use Vector3 as Color3;
+use crate::film::Film;
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum FilterKind {
+    /// Every sample counts fully towards the pixels it lands within the radius of
+    Box,
+    /// Weight falling off in a straight line to the radius
+    Tent,
+    /// A Gaussian with a standard deviation of a third of the radius
+    Gaussian,
+    /// Mitchell-Netravali with B = C = 1/3, slightly sharpening
+    Mitchell,
+    /// A Blackman-Harris window, soft with barely any ringing
+    BlackmanHarris
+}
+impl FilterKind {
+    /// The radius the filter is usually used with, in pixels.
+    pub fn default_radius(self: &FilterKind) -> f64 {
+        match self {
+            FilterKind::Box => 0.5,
+            FilterKind::Tent => 1.0,
+            FilterKind::Gaussian => 1.5,
+            FilterKind::Mitchell | FilterKind::BlackmanHarris => 2.0
+        }
+    }
+}
+
+/// How samples are weighted into the pixels around them to reconstruct the image.
+///
+/// Each sample is splatted into every pixel whose center lies within `radius` pixels of it on
+/// both axes, weighted by the filter at that offset. A box of radius 0.5 gives every sample only
+/// to the pixel it was taken in, which is a plain average.
+#[derive(Clone, Copy)]
+pub struct Filter {
+    pub kind: FilterKind,
+    pub radius: f64
+}
+impl Filter {
+    /// Weight of a sample `dx` and `dy` pixels away from a pixel's center.
+    pub fn weight(self: &Filter, dx: f64, dy: f64) -> f64 {
+        self.evaluate(dx) * self.evaluate(dy)
+    }
+    fn evaluate(self: &Filter, d: f64) -> f64 {
+        let r = self.radius;
+        // Half open, so a sample on the border between two pixels only counts towards one
+        if d < -r || d >= r {
+            return 0.0;
+        }
+        match self.kind {
+            FilterKind::Box => 1.0,
+            FilterKind::Tent => r - d.abs(),
+            FilterKind::Gaussian => {
+                // Shifted down so it reaches zero at the radius instead of stopping abruptly
+                let sigma = r / 3.0;
+                let gaussian = |x: f64| f64::exp(-x * x / (2.0 * sigma * sigma));
+                f64::max(0.0, gaussian(d) - gaussian(r))
+            }
+            FilterKind::Mitchell => {
+                let (b, c) = (1.0 / 3.0, 1.0 / 3.0);
+                let x = (2.0 * d / r).abs();
+                let polynomial = if x < 1.0 {
+                    (12.0 - 9.0 * b - 6.0 * c) * x * x * x + (-18.0 + 12.0 * b + 6.0 * c) * x * x + (6.0 - 2.0 * b)
+                } else {
+                    (-b - 6.0 * c) * x * x * x + (6.0 * b + 30.0 * c) * x * x + (-12.0 * b - 48.0 * c) * x + (8.0 * b + 24.0 * c)
+                };
+                polynomial / 6.0
+            }
+            FilterKind::BlackmanHarris => {
+                let t = PI * d / r;
+                0.35875 + 0.48829 * t.cos() + 0.14128 * (2.0 * t).cos() + 0.01168 * (3.0 * t).cos()
+            }
+        }
+    }
+    /// Adds a sample of `color` taken at `(x, y)`, measured in pixels from the film's upper left
+    /// corner, to the pixels of `film` around it.
+    pub fn splat(self: &Filter, film: &mut Film, x: f64, y: f64, color: Color3<f64>) {
+        let r = self.radius;
+        let (x0, x1) = (((x - 0.5 - r).floor() as i32).max(0), ((x - 0.5 + r).ceil() as i32).min(film.width - 1));
+        let (y0, y1) = (((y - 0.5 - r).floor() as i32).max(0), ((y - 0.5 + r).ceil() as i32).min(film.height - 1));
+        for py in y0..=y1 {
+            for px in x0..=x1 {
+                let weight = self.weight(x - (px as f64 + 0.5), y - (py as f64 + 0.5));
+                if weight != 0.0 {
+                    let pixel = film.get_mut(px, py);
+                    pixel.sum += weight * color;
+                    pixel.weight += weight;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::InnerSpace;
+    use super::*;
+
+    #[test]
+    fn box_of_half_a_pixel_is_a_plain_average() {
+        let filter = Filter { kind: FilterKind::Box, radius: 0.5 };
+        let mut film = Film::new(3, 2);
+        let samples = [
+            (0.1, 0.2, Color3::new(1.0, 0.0, 0.0)),
+            (0.9, 0.9, Color3::new(0.0, 1.0, 0.0)),
+            (0.5, 0.0, Color3::new(0.0, 0.0, 4.0)),
+            (2.0, 1.5, Color3::new(3.0, 3.0, 3.0)),
+            (2.99, 1.99, Color3::new(1.0, 2.0, 3.0))
+        ];
+        for (x, y, color) in samples {
+            filter.splat(&mut film, x, y, color);
+        }
+        for py in 0..film.height {
+            for px in 0..film.width {
+                let inside = samples.iter()
+                    .filter(|(x, y, _)| x.floor() as i32 == px && y.floor() as i32 == py)
+                    .map(|(_, _, color)| *color)
+                    .collect::<Vec<Color3<f64>>>();
+                let pixel = film.get(px, py);
+                assert_eq!(pixel.weight, inside.len() as f64);
+                if !inside.is_empty() {
+                    let average = inside.iter().fold(Color3::new(0.0, 0.0, 0.0), |sum, c| sum + c) / inside.len() as f64;
+                    assert!((pixel.mean() - average).magnitude() < 1e-12);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,176 @@
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+use cgmath::{InnerSpace, Vector3};
+
+use Vector3 as Point3;
+use crate::ray::Ray;
+use crate::util::{ray_box_intersection, Interval};
+
+/// A regular grid of heights in `[0, 1]`, sampled at `width` points along x and `depth` along z.
+///
+/// Alongside the heights it keeps a max-mipmap: level `l` stores, for every block of `2^l` by `2^l`
+/// cells, the highest point in that block. Rays are traced by descending this hierarchy and
+/// skipping every block they pass over, so only the few cells near the surface are ever tested.
+pub struct Heightfield {
+    pub width: usize,
+    pub depth: usize,
+    pub heights: Vec<f64>,
+    max_mips: Vec<Vec<f64>>
+}
+impl Heightfield {
+    pub fn new(width: usize, depth: usize, heights: Vec<f64>) -> Self {
+        assert!(width >= 2 && depth >= 2, "a heightfield needs at least 2x2 samples");
+        assert_eq!(heights.len(), width * depth);
+        let mut field = Self { width, depth, heights, max_mips: vec![] };
+
+        // Level 0 has one entry per cell, holding the highest of its four corners
+        let (mut cells_x, mut cells_z) = (width - 1, depth - 1);
+        let mut level = Vec::with_capacity(cells_x * cells_z);
+        for j in 0..cells_z {
+            for i in 0..cells_x {
+                level.push(
+                    field.height(i, j).max(field.height(i + 1, j))
+                        .max(field.height(i, j + 1)).max(field.height(i + 1, j + 1))
+                );
+            }
+        }
+        field.max_mips.push(level);
+        while cells_x > 1 || cells_z > 1 {
+            let (next_x, next_z) = (cells_x.div_ceil(2), cells_z.div_ceil(2));
+            let previous = field.max_mips.last().unwrap();
+            let mut next = vec![0.0; next_x * next_z];
+            for j in 0..cells_z {
+                for i in 0..cells_x {
+                    let parent = &mut next[(j / 2) * next_x + i / 2];
+                    *parent = f64::max(*parent, previous[j * cells_x + i]);
+                }
+            }
+            field.max_mips.push(next);
+            (cells_x, cells_z) = (next_x, next_z);
+        }
+        field
+    }
+    /// Builds a heightfield from a grayscale (or color, by luminance) image, white being highest.
+    pub fn from_image(path: &Path) -> Result<Self, Error> {
+        let image = image::open(path).map_err(|e| Error::new(ErrorKind::InvalidData, e))?.to_luma32f();
+        let (width, depth) = (image.width() as usize, image.height() as usize);
+        if width < 2 || depth < 2 {
+            return Err(Error::new(ErrorKind::InvalidData, "a heightmap needs at least 2x2 pixels"));
+        }
+        let heights = image.pixels().map(|p| p.0[0] as f64).collect();
+        Ok(Heightfield::new(width, depth, heights))
+    }
+    /// Rolling hills built from a few octaves of sine waves.
+    pub fn hills(size: usize) -> Self {
+        let mut heights = Vec::with_capacity(size * size);
+        for j in 0..size {
+            for i in 0..size {
+                let x = i as f64 / (size - 1) as f64 * 8.0;
+                let z = j as f64 / (size - 1) as f64 * 8.0;
+                let mut height = 0.0;
+                let mut amplitude = 0.5;
+                let mut frequency = 1.0;
+                for octave in 0..6 {
+                    let phase = octave as f64 * 1.7;
+                    height += amplitude * f64::sin(frequency * x + phase) * f64::cos(frequency * 1.3 * z - phase);
+                    amplitude *= 0.5;
+                    frequency *= 2.1;
+                }
+                heights.push(Interval::new(0.0, 1.0).clamp(0.5 + height));
+            }
+        }
+        Heightfield::new(size, size, heights)
+    }
+    pub fn height(self: &Heightfield, i: usize, j: usize) -> f64 {
+        self.heights[j * self.width + i]
+    }
+    /// Intersects a ray given in grid space, where sample `(i, j)` sits at `(i, height, j)`.
+    ///
+    /// Returns the hit distance and the outward surface normal in grid space, and adds one to `cost`
+    /// for every block of the max-mipmap visited.
+    pub fn intersect(self: &Heightfield, ray: &Ray, ray_t: &Interval, cost: &mut u64) -> Option<(f64, Vector3<f64>)> {
+        self.traverse(self.max_mips.len() - 1, 0, 0, ray, ray_t, cost)
+    }
+    fn traverse(self: &Heightfield, level: usize, i: usize, j: usize, ray: &Ray, ray_t: &Interval, cost: &mut u64) -> Option<(f64, Vector3<f64>)> {
+        let cells_x = self.width - 1;
+        let cells_z = self.depth - 1;
+        let size = 1 << level;
+        let (x0, z0) = (i * size, j * size);
+        if x0 >= cells_x || z0 >= cells_z {
+            return None;
+        }
+        let (x1, z1) = ((x0 + size).min(cells_x), (z0 + size).min(cells_z));
+        let level_width = cells_x.div_ceil(size);
+        let top = self.max_mips[level][j * level_width + i];
+        *cost += 1;
+        // Pad the block vertically so perfectly flat regions still have some thickness
+        let (t_enter, t_exit) = ray_box_intersection(
+            ray,
+            Point3::new(x0 as f64, -1e-6, z0 as f64),
+            Point3::new(x1 as f64, top + 1e-6, z1 as f64)
+        )?;
+        if t_exit < ray_t.min || t_enter > ray_t.max {
+            return None;
+        }
+        if level == 0 {
+            return self.intersect_cell(i, j, ray, ray_t);
+        }
+        // Children don't overlap, so visiting them in order of entry finds the nearest hit first
+        let mut children = vec![];
+        for (ci, cj) in [(2 * i, 2 * j), (2 * i + 1, 2 * j), (2 * i, 2 * j + 1), (2 * i + 1, 2 * j + 1)] {
+            let child_size = size / 2;
+            let (cx0, cz0) = (ci * child_size, cj * child_size);
+            if cx0 >= cells_x || cz0 >= cells_z {
+                continue;
+            }
+            let (cx1, cz1) = ((cx0 + child_size).min(cells_x), (cz0 + child_size).min(cells_z));
+            let bounds = ray_box_intersection(
+                ray,
+                Point3::new(cx0 as f64, f64::NEG_INFINITY, cz0 as f64),
+                Point3::new(cx1 as f64, f64::INFINITY, cz1 as f64)
+            );
+            if let Some((t_enter, _)) = bounds {
+                children.push((t_enter, ci, cj));
+            }
+        }
+        children.sort_by(|a, b| a.0.total_cmp(&b.0));
+        children.into_iter().find_map(|(_, ci, cj)| self.traverse(level - 1, ci, cj, ray, ray_t, cost))
+    }
+    fn intersect_cell(self: &Heightfield, i: usize, j: usize, ray: &Ray, ray_t: &Interval) -> Option<(f64, Vector3<f64>)> {
+        let corner = |di: usize, dj: usize| Point3::new((i + di) as f64, self.height(i + di, j + dj), (j + dj) as f64);
+        let p00 = corner(0, 0);
+        let p10 = corner(1, 0);
+        let p01 = corner(0, 1);
+        let p11 = corner(1, 1);
+        [intersect_triangle(ray, p00, p01, p11), intersect_triangle(ray, p00, p11, p10)]
+            .into_iter()
+            .flatten()
+            .filter(|(t, _)| ray_t.surrounds(*t))
+            .min_by(|a, b| a.0.total_cmp(&b.0))
+    }
+}
+
+/// Möller-Trumbore intersection, returning the distance and the normal facing up (+y).
+fn intersect_triangle(ray: &Ray, a: Point3<f64>, b: Point3<f64>, c: Point3<f64>) -> Option<(f64, Vector3<f64>)> {
+    let edge1 = b - a;
+    let edge2 = c - a;
+    let p = ray.direction.cross(edge2);
+    let determinant = edge1.dot(p);
+    if determinant.abs() < 1e-12 {
+        return None;
+    }
+    let inverse = 1.0 / determinant;
+    let s = ray.origin - a;
+    let u = s.dot(p) * inverse;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let q = s.cross(edge1);
+    let v = ray.direction.dot(q) * inverse;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+    let t = edge2.dot(q) * inverse;
+    let normal = edge1.cross(edge2);
+    Some((t, if normal.y < 0.0 { -normal } else { normal }))
+}
@@ -1,17 +1,29 @@
 use std::collections::HashMap;
-use cgmath::{ElementWise, InnerSpace, Vector3};
+use std::sync::Arc;
+use cgmath::{ElementWise, InnerSpace, Vector2, Vector3, VectorSpace};
 use cgmath::num_traits::Pow;
 use image::DynamicImage;
 use rand::Rng;
 
 use Vector3 as Point3;
 use Vector3 as Color3;
+use Vector2 as Point2;
 
 use crate::material::Material;
-use crate::util::{Interval, random_vector, random_vector_bounded, unit_vector, vector_length};
-use crate::hittable::Hittable::{Circle, Triangle};
+use crate::util::{Interval, random_unit_vector, random_vector, random_vector_bounded, ray_box_intersection, unit_vector, vector_length};
+use crate::hittable::Hittable::{Circle, Csg, Cuboid, Curve, Cylinder, Implicit, Terrain, Triangle, Volume};
+use crate::curve::{bezier_tangent, intersect_curve, CurveShape};
+use crate::heightfield::Heightfield;
+use crate::csg::CsgOperation;
 use crate::ray::Ray;
 use crate::util;
+use crate::volume::VoxelGrid;
+use crate::sdf::Sdf;
+
+/// How close sphere tracing has to get to an implicit surface to count as a hit.
+const SURFACE_EPSILON: f64 = 1e-4;
+/// How many steps sphere tracing takes before giving up on a ray.
+const MAX_MARCH_STEPS: usize = 512;
 
 pub struct HitRecord {
     pub point: Point3<f64>,
@@ -19,10 +31,12 @@ pub struct HitRecord {
     pub t: f64,
     pub material: Material,
     pub front_face: bool,
+    /// Surface coordinates of the hit, where the primitive defines them
+    pub uv: Point2<f64>,
     pub object: Hittable
 }
 impl HitRecord {
-    fn set_face_normal(self: &mut HitRecord, ray: &Ray, outward_normal: Vector3<f64>) {
+    pub fn set_face_normal(self: &mut HitRecord, ray: &Ray, outward_normal: Vector3<f64>) {
         self.front_face = ray.direction.dot(outward_normal) < 0.0;
         self.normal = if self.front_face { outward_normal } else { -outward_normal };
     }
@@ -30,12 +44,34 @@ impl HitRecord {
 }
 pub enum Hittable {
     Circle { center: Vector3<f64>, radius: f64, material: Material },
-    Triangle { a: Point3<f64>, b: Point3<f64>, c: Point3<f64>, material: Material }
+    Triangle { a: Point3<f64>, b: Point3<f64>, c: Point3<f64>, material: Material },
+    /// A heterogeneous participating medium filling the box between `min` and `max`.
+    ///
+    /// `density` scales the grid's density and `emission` scales the blackbody glow of the grid's
+    /// temperature, if it has one.
+    Volume { grid: Arc<VoxelGrid>, min: Point3<f64>, max: Point3<f64>, density: f64, albedo: Color3<f64>, emission: f64 },
+    /// An axis aligned box between the corners `min` and `max`.
+    Cuboid { min: Point3<f64>, max: Point3<f64>, material: Material },
+    /// A capped cylinder whose axis runs from the center of `base` to the center of `top`.
+    Cylinder { base: Point3<f64>, top: Point3<f64>, radius: f64, material: Material },
+    /// A boolean combination of two closed solids (`Circle`, `Cuboid`, `Cylinder` or another `Csg`).
+    Csg { operation: CsgOperation, left: Box<Hittable>, right: Box<Hittable> },
+    /// The zero set of a signed distance field, sphere traced inside the box between `min` and `max`.
+    Implicit { field: Arc<Sdf>, min: Point3<f64>, max: Point3<f64>, material: Material },
+    /// A heightfield stretched over the box between `min` and `max`, heights of 0 and 1 mapping to
+    /// `min.y` and `max.y`.
+    Terrain { field: Arc<Heightfield>, min: Point3<f64>, max: Point3<f64>, material: Material },
+    /// A cubic Bézier curve whose width varies linearly from `widths.0` at the first control
+    /// point to `widths.1` at the last one.
+    Curve { points: [Point3<f64>; 4], widths: (f64, f64), shape: CurveShape, material: Material }
 }
 impl Hittable {
-    pub fn hit(self: &Hittable, ray: &Ray, ray_t: Interval) -> Option<HitRecord> {
+    /// The nearest hit of `ray` within `ray_t`, adding the work it took to `cost`: one for every
+    /// primitive or bounding box tested and for every step of a march through a shape.
+    pub fn hit(self: &Hittable, ray: &Ray, ray_t: Interval, rng: &mut impl Rng, cost: &mut u64) -> Option<HitRecord> {
         match self {
             Circle { center, radius, material } => {
+                *cost += 1;
                 let oc = ray.origin - center;
                 let a = util::vector_length_squared(ray.direction);
                 let half_b = oc.dot(ray.direction);
@@ -53,6 +89,7 @@ impl Hittable {
                             t: root,
                             material: material.clone(),
                             front_face: true,
+                            uv: sphere_uv(outward_normal),
                             object: Circle { center: *center, radius: *radius, material: material.clone() }
                         };
                         record.set_face_normal(ray, outward_normal);
@@ -62,6 +99,7 @@ impl Hittable {
                 }
             },
             Triangle { a, b, c, material } => {
+                *cost += 1;
                 let ba = b - a;
                 let ca = c - a;
                 let n = ba.cross(ca);
@@ -92,12 +130,16 @@ impl Hittable {
                     && normal.dot(edge1.cross(c1)) > 0.0
                     && normal.dot(edge2.cross(c2)) > 0.0
                 {
+                    // Barycentric weights of b and c
+                    let area = n.magnitude2();
+                    let uv = Point2::new(n.dot(c0.cross(ca)) / area, n.dot(ba.cross(c0)) / area);
                     let mut record = HitRecord {
                         point: intersection,
                         normal,
                         t,
                         material: material.clone(),
                         front_face: true,
+                        uv,
                         object: Triangle { a: *a, b: *b, c: *c, material: material.clone() }
                     };
                     record.set_face_normal(ray, normal);
@@ -106,8 +148,176 @@ impl Hittable {
                     None
                 }
             }
+            Volume { grid, min, max, density, albedo, emission } => {
+                *cost += 1;
+                let (t_enter, t_exit) = ray_box_intersection(ray, *min, *max)?;
+                let t_min = f64::max(t_enter, ray_t.min);
+                let t_max = f64::min(t_exit, ray_t.max);
+                let majorant = density * grid.max_density;
+                if t_min >= t_max || majorant <= 0.0 {
+                    return None
+                }
+
+                // Delta tracking: sample tentative collisions against the majorant and accept
+                // each one with probability density / majorant. Paths only reach lights by
+                // scattering into them, never through shadow rays, so there is no transmittance
+                // to estimate with ratio tracking.
+                let ray_length = vector_length(ray.direction);
+                let extent = max - min;
+                let mut t = t_min;
+                loop {
+                    *cost += 1;
+                    let u: f64 = rng.gen();
+                    t -= f64::ln(1.0 - u) / (majorant * ray_length);
+                    if t >= t_max {
+                        return None
+                    }
+                    let p = ray.at(t) - min;
+                    let local = Point3::new(p.x / extent.x, p.y / extent.y, p.z / extent.z);
+                    if density * grid.density(local) / majorant > rng.gen() {
+                        let emitted = match grid.temperature(local) {
+                            Some(kelvin) => *emission * util::blackbody(kelvin),
+                            None => Color3::new(0.0, 0.0, 0.0)
+                        };
+                        return Some(HitRecord {
+                            point: ray.at(t),
+                            normal: random_unit_vector(rng),
+                            t,
+                            material: Material::Isotropic { albedo: *albedo, emission: emitted },
+                            front_face: true,
+                            uv: Point2::new(0.0, 0.0),
+                            object: Volume {
+                                grid: grid.clone(),
+                                min: *min,
+                                max: *max,
+                                density: *density,
+                                albedo: *albedo,
+                                emission: *emission
+                            }
+                        })
+                    }
+                }
+            }
+            Cuboid { .. } | Cylinder { .. } | Csg { .. } => self.solid_hit(ray, ray_t, cost),
+            Implicit { field, min, max, material } => {
+                *cost += 1;
+                let (t_enter, t_exit) = ray_box_intersection(ray, *min, *max)?;
+                let t_max = f64::min(t_exit, ray_t.max);
+                let ray_length = vector_length(ray.direction);
+                let mut t = f64::max(t_enter, ray_t.min);
+
+                // Rays scattered off the surface start on it, so step clear of it first
+                let mut steps = 0;
+                while steps < MAX_MARCH_STEPS && t < t_max && field.distance(ray.at(t)).abs() < SURFACE_EPSILON {
+                    t += 2.0 * SURFACE_EPSILON / ray_length;
+                    steps += 1;
+                }
+                *cost += steps as u64;
+
+                // Sphere tracing: the distance to the surface is always a safe step
+                while steps < MAX_MARCH_STEPS && t < t_max {
+                    *cost += 1;
+                    let point = ray.at(t);
+                    let distance = field.distance(point).abs();
+                    if distance < SURFACE_EPSILON {
+                        let outward_normal = field.normal(point);
+                        let mut record = HitRecord {
+                            point,
+                            normal: outward_normal,
+                            t,
+                            material: material.clone(),
+                            front_face: true,
+                            uv: Point2::new(0.0, 0.0),
+                            object: self.clone()
+                        };
+                        record.set_face_normal(ray, outward_normal);
+                        return Some(record)
+                    }
+                    t += distance / ray_length;
+                    steps += 1;
+                }
+                None
+            }
+            Terrain { field, min, max, material } => {
+                // World units per grid unit. The mapping is affine, so distances along the ray carry over.
+                let scale = Vector3::new(
+                    (max.x - min.x) / (field.width - 1) as f64,
+                    max.y - min.y,
+                    (max.z - min.z) / (field.depth - 1) as f64
+                );
+                let local_ray = Ray {
+                    origin: (ray.origin - min).div_element_wise(scale),
+                    direction: ray.direction.div_element_wise(scale),
+                    time: ray.time
+                };
+                let (t, local_normal) = field.intersect(&local_ray, &ray_t, cost)?;
+                let local_point = local_ray.at(t);
+                // Normals transform by the inverse transpose, which for a scale is the inverse scale
+                let outward_normal = unit_vector(local_normal.div_element_wise(scale));
+                let mut record = HitRecord {
+                    point: ray.at(t),
+                    normal: outward_normal,
+                    t,
+                    material: material.clone(),
+                    front_face: true,
+                    uv: Point2::new(local_point.x / (field.width - 1) as f64, local_point.z / (field.depth - 1) as f64),
+                    object: self.clone()
+                };
+                record.set_face_normal(ray, outward_normal);
+                Some(record)
+            }
+            Curve { points, widths, shape, material } => {
+                let hit = intersect_curve(ray, points, *widths, &ray_t, cost)?;
+                let tangent = unit_vector(bezier_tangent(points, hit.u));
+                let toward_ray = -unit_vector(ray.direction);
+                let across = unit_vector(tangent.cross(toward_ray));
+                let facing = across.cross(tangent);
+                let normal = match shape {
+                    CurveShape::Ribbon => facing,
+                    CurveShape::Cylinder => {
+                        // Bend the normal around the tube, from one edge of the strip to the other
+                        let theta = (2.0 * hit.v - 1.0) * std::f64::consts::FRAC_PI_2;
+                        theta.cos() * facing + theta.sin() * across
+                    }
+                };
+                Some(HitRecord {
+                    point: ray.at(hit.t),
+                    normal,
+                    t: hit.t,
+                    material: material.clone(),
+                    front_face: true,
+                    uv: Point2::new(hit.u, hit.v),
+                    object: self.clone()
+                })
+            }
         }
     }
+    pub fn clone(self: &Hittable) -> Hittable {
+        match self {
+            Circle { center, radius, material } => Circle { center: *center, radius: *radius, material: material.clone() },
+            Triangle { a, b, c, material } => Triangle { a: *a, b: *b, c: *c, material: material.clone() },
+            Volume { grid, min, max, density, albedo, emission } => Volume {
+                grid: grid.clone(),
+                min: *min,
+                max: *max,
+                density: *density,
+                albedo: *albedo,
+                emission: *emission
+            },
+            Cuboid { min, max, material } => Cuboid { min: *min, max: *max, material: material.clone() },
+            Cylinder { base, top, radius, material } => Cylinder { base: *base, top: *top, radius: *radius, material: material.clone() },
+            Csg { operation, left, right } => Csg { operation: *operation, left: Box::new(Hittable::clone(left)), right: Box::new(Hittable::clone(right)) },
+            Implicit { field, min, max, material } => Implicit { field: field.clone(), min: *min, max: *max, material: material.clone() },
+            Terrain { field, min, max, material } => Terrain { field: field.clone(), min: *min, max: *max, material: material.clone() },
+            Curve { points, widths, shape, material } => Curve { points: *points, widths: *widths, shape: *shape, material: material.clone() }
+        }
+    }
+}
+/// Longitude and latitude of a point on a unit sphere, both scaled to `[0, 1]`.
+pub fn sphere_uv(p: Vector3<f64>) -> Point2<f64> {
+    let theta = f64::acos(-p.y);
+    let phi = f64::atan2(-p.z, p.x) + std::f64::consts::PI;
+    Point2::new(phi / (2.0 * std::f64::consts::PI), theta / std::f64::consts::PI)
 }
 fn find_nearest_root(a: f64, half_b: f64, discriminant: f64, ray_t: Interval) -> Option<f64> {
     let sqrtd = f64::sqrt(discriminant);
@@ -122,28 +332,42 @@ fn find_nearest_root(a: f64, half_b: f64, discriminant: f64, ray_t: Interval) ->
 }
 pub struct HittableList {
     pub hittables: Vec<Hittable>,
+    /// The object each of `hittables` is part of, so all the faces of a mesh share one
+    pub objects: Vec<u32>,
     pub textures: HashMap<String, DynamicImage>
 }
 impl HittableList {
+    /// Adds `hittable` as an object of its own.
     pub fn add(self: &mut HittableList, hittable: Hittable) {
-        self.hittables.push(hittable);
+        self.add_object(vec![hittable]);
+    }
+    /// Adds `parts` as one object, such as the faces of a mesh.
+    pub fn add_object(self: &mut HittableList, parts: Vec<Hittable>) {
+        let object = self.objects.last().map_or(0, |last| last + 1);
+        self.objects.extend(parts.iter().map(|_| object));
+        self.hittables.extend(parts);
+    }
+    pub fn hit(self: &HittableList, ray: &Ray, ray_t: Interval, rng: &mut impl Rng) -> Option<HitRecord> {
+        self.hit_object(ray, ray_t, rng, &mut 0).map(|(_, hit)| hit)
     }
-    pub fn hit(self: &HittableList, ray: &Ray, ray_t: Interval) -> Option<HitRecord> {
-        let mut hit_record: Option<HitRecord> = None;
+    /// Like `hit`, but also says which object was hit, and adds the work it took to `cost` as
+    /// `Hittable::hit` does.
+    pub fn hit_object(self: &HittableList, ray: &Ray, ray_t: Interval, rng: &mut impl Rng, cost: &mut u64) -> Option<(u32, HitRecord)> {
+        let mut hit_record: Option<(u32, HitRecord)> = None;
         let mut closest_t_hit = ray_t.max;
-        for hittable in &self.hittables {
-            match hittable.hit(ray, Interval::new(ray_t.min, closest_t_hit)) {
+        for (hittable, object) in self.hittables.iter().zip(&self.objects) {
+            match hittable.hit(ray, Interval::new(ray_t.min, closest_t_hit), rng, cost) {
                 Some(hit) => {
                     closest_t_hit = hit.t;
-                    hit_record = Some(hit);
+                    hit_record = Some((*object, hit));
                 }
                 None => continue
             }
         }
         hit_record
     }
-    pub fn random_spheres() -> Self {
-        let mut world = Self { hittables: vec![], textures: HashMap::new() };
+    pub fn random_spheres(rng: &mut impl Rng) -> Self {
+        let mut world = Self { hittables: vec![], objects: vec![], textures: HashMap::new() };
         world.add(
             Circle {
                 center: Vector3::new(0.0, -1000.0, 0.0),
@@ -151,7 +375,6 @@ impl HittableList {
                 material: Material::Lambertian { albedo: Color3::new(0.5, 0.5, 0.5) }
             }
         );
-        let mut rng = rand::thread_rng();
         let offset_point = Point3::new(4.0, 0.2, 0.0);
         for a in -11..11 {
             for b in -11..11 {
@@ -161,7 +384,7 @@ impl HittableList {
                 let center = Point3::new(a as f64 + 0.9*a_variance, 0.2, b as f64 + 0.9*b_variance);
                 if vector_length(center - offset_point) > 0.9 {
                     if choice == 0 {
-                        let albedo = random_vector().mul_element_wise(random_vector());
+                        let albedo = random_vector(rng).mul_element_wise(random_vector(rng));
                         world.add(
                             Circle {
                                 center,
@@ -170,7 +393,7 @@ impl HittableList {
                             }
                         );
                     } else if choice == 1 {
-                        let albedo = random_vector_bounded(0.5, 1.0);
+                        let albedo = random_vector_bounded(0.5, 1.0, rng);
                         let fuzz = rng.gen_range(0.0..0.5);
                         world.add(
                             Circle {
@@ -217,5 +440,249 @@ impl HittableList {
         );
         world
     }
+    pub fn smoke(grid: VoxelGrid) -> Self {
+        let mut world = Self { hittables: vec![], objects: vec![], textures: HashMap::new() };
+        world.add(
+            Circle {
+                center: Vector3::new(0.0, -1000.0, 0.0),
+                radius: 1000.0,
+                material: Material::Lambertian { albedo: Color3::new(0.5, 0.5, 0.5) }
+            }
+        );
+        world.add(
+            Volume {
+                grid: Arc::new(grid),
+                min: Point3::new(-1.5, 0.0, -1.5),
+                max: Point3::new(1.5, 3.0, 1.5),
+                density: 2.0,
+                albedo: Color3::new(0.8, 0.8, 0.8),
+                emission: 1.0
+            }
+        );
+        world
+    }
+    pub fn csg() -> Self {
+        let mut world = Self { hittables: vec![], objects: vec![], textures: HashMap::new() };
+        world.add(
+            Circle {
+                center: Vector3::new(0.0, -1000.0, 0.0),
+                radius: 1000.0,
+                material: Material::Lambertian { albedo: Color3::new(0.5, 0.5, 0.5) }
+            }
+        );
 
+        // A steel plate with a grid of drilled holes
+        let steel = Material::Metal { albedo: Color3::new(0.7, 0.7, 0.75), fuzz: 0.2 };
+        let mut plate = Cuboid {
+            min: Point3::new(-3.0, 0.0, -1.5),
+            max: Point3::new(0.0, 0.3, 1.5),
+            material: steel.clone()
+        };
+        for x in [-2.4, -1.5, -0.6] {
+            for z in [-0.9, 0.0, 0.9] {
+                plate = Csg {
+                    operation: CsgOperation::Difference,
+                    left: Box::new(plate),
+                    right: Box::new(Cylinder {
+                        base: Point3::new(x, -0.1, z),
+                        top: Point3::new(x, 0.4, z),
+                        radius: 0.25,
+                        material: steel.clone()
+                    })
+                };
+            }
+        }
+        world.add(plate);
+
+        // A biconvex lens: the overlap of two large spheres
+        let glass = Material::Glass { refraction_index: 1.5 };
+        world.add(
+            Csg {
+                operation: CsgOperation::Intersection,
+                left: Box::new(Circle { center: Point3::new(1.6, 1.0, 0.0), radius: 2.0, material: glass.clone() }),
+                right: Box::new(Circle { center: Point3::new(-1.6, 1.0, 0.0), radius: 2.0, material: glass.clone() })
+            }
+        );
+
+        // A die: a rounded cube carved out with spherical pips
+        let ivory = Material::Lambertian { albedo: Color3::new(0.9, 0.85, 0.75) };
+        let mut die = Csg {
+            operation: CsgOperation::Intersection,
+            left: Box::new(Cuboid { min: Point3::new(1.5, 0.0, -0.5), max: Point3::new(2.5, 1.0, 0.5), material: ivory.clone() }),
+            right: Box::new(Circle { center: Point3::new(2.0, 0.5, 0.0), radius: 0.7, material: ivory.clone() })
+        };
+        for (y, z) in [(0.25, -0.25), (0.5, 0.0), (0.75, 0.25)] {
+            die = Csg {
+                operation: CsgOperation::Difference,
+                left: Box::new(die),
+                right: Box::new(Circle {
+                    center: Point3::new(2.5, y, z),
+                    radius: 0.1,
+                    material: Material::Lambertian { albedo: Color3::new(0.1, 0.1, 0.1) }
+                })
+            };
+        }
+        world.add(
+            Csg {
+                operation: CsgOperation::Union,
+                left: Box::new(die),
+                right: Box::new(Cylinder {
+                    base: Point3::new(2.0, 0.0, 1.2),
+                    top: Point3::new(2.0, 1.2, 1.2),
+                    radius: 0.3,
+                    material: Material::Metal { albedo: Color3::new(0.8, 0.6, 0.3), fuzz: 0.1 }
+                })
+            }
+        );
+        world
+    }
+    pub fn implicit() -> Self {
+        let mut world = Self { hittables: vec![], objects: vec![], textures: HashMap::new() };
+        world.add(
+            Circle {
+                center: Vector3::new(0.0, -1000.0, 0.0),
+                radius: 1000.0,
+                material: Material::Lambertian { albedo: Color3::new(0.5, 0.5, 0.5) }
+            }
+        );
+        world.add(
+            Implicit {
+                field: Arc::new(Sdf::Mandelbulb { center: Point3::new(0.0, 1.2, 0.0), scale: 1.0, power: 8.0, iterations: 12 }),
+                min: Point3::new(-1.3, -0.1, -1.3),
+                max: Point3::new(1.3, 2.5, 1.3),
+                material: Material::Lambertian { albedo: Color3::new(0.8, 0.45, 0.2) }
+            }
+        );
+        // Metaballs blended into one blob
+        let blob = Sdf::SmoothUnion {
+            a: Box::new(Sdf::SmoothUnion {
+                a: Box::new(Sdf::Sphere { center: Point3::new(-3.0, 0.6, 0.0), radius: 0.6 }),
+                b: Box::new(Sdf::Sphere { center: Point3::new(-2.3, 0.9, 0.3), radius: 0.45 }),
+                k: 0.4
+            }),
+            b: Box::new(Sdf::Sphere { center: Point3::new(-2.7, 1.4, -0.2), radius: 0.35 }),
+            k: 0.4
+        };
+        world.add(
+            Implicit {
+                field: Arc::new(blob),
+                min: Point3::new(-4.0, -0.1, -1.0),
+                max: Point3::new(-1.5, 2.0, 1.2),
+                material: Material::Glass { refraction_index: 1.5 }
+            }
+        );
+        // A twisted pillar next to a torus
+        world.add(
+            Implicit {
+                field: Arc::new(Sdf::Translate {
+                    offset: Vector3::new(2.6, 0.0, 0.0),
+                    child: Box::new(Sdf::Union {
+                        a: Box::new(Sdf::Twist {
+                            rate: 1.2,
+                            child: Box::new(Sdf::Box { center: Point3::new(0.0, 0.9, 0.0), half_extents: Vector3::new(0.3, 0.9, 0.3) })
+                        }),
+                        b: Box::new(Sdf::Torus { center: Point3::new(0.0, 0.15, 0.0), major_radius: 0.7, minor_radius: 0.15 })
+                    })
+                }),
+                min: Point3::new(1.7, -0.1, -0.9),
+                max: Point3::new(3.5, 1.9, 0.9),
+                material: Material::Metal { albedo: Color3::new(0.7, 0.6, 0.5), fuzz: 0.05 }
+            }
+        );
+        // A row of small spheres repeated in front of everything
+        world.add(
+            Implicit {
+                field: Arc::new(Sdf::Translate {
+                    offset: Vector3::new(0.0, 0.15, 2.0),
+                    child: Box::new(Sdf::Repeat {
+                        period: Vector3::new(0.6, 10.0, 10.0),
+                        child: Box::new(Sdf::Sphere { center: Point3::new(0.0, 0.0, 0.0), radius: 0.15 })
+                    })
+                }),
+                min: Point3::new(-4.0, 0.0, 1.8),
+                max: Point3::new(4.0, 0.3, 2.2),
+                material: Material::Metal { albedo: Color3::new(0.8, 0.8, 0.9), fuzz: 0.0 }
+            }
+        );
+        world
+    }
+    pub fn terrain(field: Heightfield) -> Self {
+        let mut world = Self { hittables: vec![], objects: vec![], textures: HashMap::new() };
+        world.add(
+            Terrain {
+                field: Arc::new(field),
+                min: Point3::new(-10.0, 0.0, -10.0),
+                max: Point3::new(10.0, 2.5, 10.0),
+                material: Material::Lambertian { albedo: Color3::new(0.45, 0.5, 0.35) }
+            }
+        );
+        // A lake filling the valleys
+        world.add(
+            Cuboid {
+                min: Point3::new(-10.0, -1.0, -10.0),
+                max: Point3::new(10.0, 0.6, 10.0),
+                material: Material::Metal { albedo: Color3::new(0.6, 0.7, 0.8), fuzz: 0.05 }
+            }
+        );
+        world
+    }
+    pub fn hair(rng: &mut impl Rng) -> Self {
+        let mut world = Self { hittables: vec![], objects: vec![], textures: HashMap::new() };
+        world.add(
+            Circle {
+                center: Vector3::new(0.0, -1000.0, 0.0),
+                radius: 1000.0,
+                material: Material::Lambertian { albedo: Color3::new(0.5, 0.5, 0.5) }
+            }
+        );
+
+        // A furry ball: strands grow out of the surface and droop under gravity
+        let center = Point3::new(-1.2, 1.0, 0.0);
+        let fur = Material::Hair { color: Color3::new(0.75, 0.5, 0.25), roughness: 0.3 };
+        world.add(Circle { center, radius: 0.8, material: Material::Lambertian { albedo: Color3::new(0.3, 0.18, 0.08) } });
+        for _ in 0..300 {
+            let direction = random_unit_vector(rng);
+            let root = center + 0.78 * direction;
+            let length = rng.gen_range(0.35..0.5);
+            let droop = Vector3::new(0.0, -0.25 * length, 0.0);
+            world.add(
+                Curve {
+                    points: [
+                        root,
+                        root + direction * length / 3.0,
+                        root + direction * 2.0 * length / 3.0 + droop / 2.0,
+                        root + direction * length + droop
+                    ],
+                    widths: (0.02, 0.004),
+                    shape: CurveShape::Cylinder,
+                    material: fur.clone()
+                }
+            );
+        }
+
+        // A paint brush: a wooden handle ending in a tuft of flat bristles
+        world.add(
+            Cylinder {
+                base: Point3::new(0.6, 0.15, 0.0),
+                top: Point3::new(2.2, 0.15, 0.0),
+                radius: 0.1,
+                material: Material::Lambertian { albedo: Color3::new(0.6, 0.1, 0.1) }
+            }
+        );
+        let bristles = Material::Hair { color: Color3::new(0.2, 0.15, 0.1), roughness: 0.2 };
+        for _ in 0..80 {
+            let offset = Vector3::new(0.0, rng.gen_range(-0.08..0.08), rng.gen_range(-0.08..0.08));
+            let start = Point3::new(0.6, 0.15, 0.0) + offset;
+            let end = Point3::new(0.05, 0.05 + 0.5 * offset.y, 2.0 * offset.z);
+            world.add(
+                Curve {
+                    points: [start, start.lerp(end, 1.0 / 3.0), start.lerp(end, 2.0 / 3.0) + Vector3::new(0.0, 0.03, 0.0), end],
+                    widths: (0.012, 0.006),
+                    shape: CurveShape::Ribbon,
+                    material: bristles.clone()
+                }
+            );
+        }
+        world
+    }
 }
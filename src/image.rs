@@ -1,16 +1,18 @@
+#[derive(Clone)]
 pub struct Image {
     pub width: i32,
     pub height: i32,
+    #[allow(dead_code)]
     pub aspect_ratio: f64,
-    pub samples_per_pixel: i32
+    pub samples_per_pixel: u32
 }
 impl Image {
-    pub fn new(width: i32, aspect_ratio: f64) -> Self {
+    pub fn new(width: i32, aspect_ratio: f64, samples_per_pixel: u32) -> Self {
         Image {
             width,
             height: (width as f64 / aspect_ratio) as i32,
             aspect_ratio,
-            samples_per_pixel: 100
+            samples_per_pixel
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,124 @@
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+use cgmath::Vector3;
+
+use Vector3 as Color3;
+
+/// The largest table the `.cube` format allows along each axis.
+const MAX_SIZE: usize = 256;
+
+/// A 3D color lookup table, as exported by grading tools in the `.cube` format, applied to
+/// display encoded colors as a final grade.
+pub struct Lut {
+    size: usize,
+    domain_min: Color3<f64>,
+    domain_max: Color3<f64>,
+    /// Output colors with red changing fastest, then green, then blue
+    table: Vec<Color3<f64>>
+}
+impl Lut {
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        let invalid = |message: String| Error::new(ErrorKind::InvalidData, format!("{}: {}", path.display(), message));
+        let mut size = None;
+        let mut domain_min = Color3::new(0.0, 0.0, 0.0);
+        let mut domain_max = Color3::new(1.0, 1.0, 1.0);
+        let mut table = vec![];
+        for (number, line) in fs::read_to_string(path)?.lines().enumerate() {
+            let tokens = line.split_whitespace().collect::<Vec<&str>>();
+            let numbers = |tokens: &[&str]| -> Result<Color3<f64>, Error> {
+                match tokens.iter().map(|t| t.parse::<f64>()).collect::<Vec<_>>().as_slice() {
+                    [Ok(r), Ok(g), Ok(b)] => Ok(Color3::new(*r, *g, *b)),
+                    _ => Err(invalid(format!("line {}: expected three numbers", number + 1)))
+                }
+            };
+            match tokens.as_slice() {
+                [] => {}
+                [comment, ..] if comment.starts_with('#') => {}
+                ["TITLE", ..] => {}
+                ["LUT_3D_SIZE", n] => size = Some(n.parse::<usize>().map_err(|e| invalid(e.to_string()))?),
+                ["LUT_1D_SIZE", ..] => return Err(invalid(String::from("only 3D LUTs are supported"))),
+                ["DOMAIN_MIN", values @ ..] => domain_min = numbers(values)?,
+                ["DOMAIN_MAX", values @ ..] => domain_max = numbers(values)?,
+                // More entries than the largest table can hold means this isn't a LUT at all
+                _ if table.len() == MAX_SIZE * MAX_SIZE * MAX_SIZE => return Err(invalid(String::from("too many entries"))),
+                _ => table.push(numbers(&tokens)?)
+            }
+        }
+        let size = size.ok_or_else(|| invalid(String::from("missing LUT_3D_SIZE")))?;
+        if !(2..=MAX_SIZE).contains(&size) {
+            return Err(invalid(format!("LUT_3D_SIZE must be from 2 to {}, not {}", MAX_SIZE, size)));
+        }
+        if table.len() != size * size * size {
+            return Err(invalid(format!("expected {} entries, found {}", size * size * size, table.len())));
+        }
+        if domain_min.x >= domain_max.x || domain_min.y >= domain_max.y || domain_min.z >= domain_max.z {
+            return Err(invalid(String::from("DOMAIN_MIN must be below DOMAIN_MAX on every channel")));
+        }
+        Ok(Lut { size, domain_min, domain_max, table })
+    }
+    /// `color` looked up in the table, interpolating trilinearly between entries.
+    pub fn apply(self: &Lut, color: Color3<f64>) -> Color3<f64> {
+        let n = self.size;
+        // Position in the table along each axis, and the entries on either side of it
+        let position = |c: f64, min: f64, max: f64| {
+            let x = ((c - min) / (max - min)).clamp(0.0, 1.0) * (n - 1) as f64;
+            let i = (x.floor() as usize).min(n - 2);
+            (i, x - i as f64)
+        };
+        let (r, fr) = position(color.x, self.domain_min.x, self.domain_max.x);
+        let (g, fg) = position(color.y, self.domain_min.y, self.domain_max.y);
+        let (b, fb) = position(color.z, self.domain_min.z, self.domain_max.z);
+        let entry = |dr: usize, dg: usize, db: usize| self.table[((b + db) * n + g + dg) * n + r + dr];
+        let lerp = |a: Color3<f64>, b: Color3<f64>, t: f64| a + (b - a) * t;
+        let along_r = |dg, db| lerp(entry(0, dg, db), entry(1, dg, db), fr);
+        let along_g = |db| lerp(along_r(0, db), along_r(1, db), fg);
+        lerp(along_g(0), along_g(1), fb)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::InnerSpace;
+    use super::*;
+
+    /// Writes a `.cube` file holding `entries` and opens it.
+    fn open_cube(name: &str, size: usize, entries: &[Color3<f64>]) -> Result<Lut, Error> {
+        let mut cube = format!("# test table\nTITLE \"{}\"\nLUT_3D_SIZE {}\n\n", name, size);
+        for entry in entries {
+            cube += &format!("{} {} {}\n", entry.x, entry.y, entry.z);
+        }
+        let path = std::env::temp_dir().join(format!("{}-{}.cube", name, std::process::id()));
+        fs::write(&path, cube)?;
+        let lut = Lut::open(&path);
+        fs::remove_file(&path)?;
+        lut
+    }
+
+    #[test]
+    fn identity_table_leaves_colors_alone() {
+        let n = 5;
+        let step = |i: usize| i as f64 / (n - 1) as f64;
+        let entries = (0..n * n * n).map(|i| Color3::new(step(i % n), step(i / n % n), step(i / (n * n)))).collect::<Vec<_>>();
+        let lut = open_cube("identity", n, &entries).unwrap();
+        for color in [Color3::new(0.0, 0.0, 0.0), Color3::new(1.0, 1.0, 1.0), Color3::new(0.1, 0.55, 0.93), Color3::new(0.7, 0.25, 0.4)] {
+            assert!((lut.apply(color) - color).magnitude() < 1e-12, "{:?} became {:?}", color, lut.apply(color));
+        }
+    }
+
+    #[test]
+    fn open_checks_the_number_of_entries() {
+        assert!(open_cube("short", 2, &[Color3::new(0.0, 0.0, 0.0); 7]).is_err());
+        assert!(open_cube("huge", 1 << 22, &[Color3::new(0.0, 0.0, 0.0); 8]).is_err());
+    }
+
+    #[test]
+    fn open_rejects_empty_domains() {
+        let path = std::env::temp_dir().join(format!("empty-domain-{}.cube", std::process::id()));
+        let entries = "0 0 0\n".repeat(8);
+        fs::write(&path, format!("LUT_3D_SIZE 2\nDOMAIN_MIN 0 0.5 0\nDOMAIN_MAX 1 0.5 1\n{}", entries)).unwrap();
+        let lut = Lut::open(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(lut.is_err());
+    }
+}
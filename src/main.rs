@@ -7,19 +7,232 @@ mod camera;
 mod material;
 mod model;
 mod scene;
+mod volume;
+mod csg;
+mod sdf;
+mod heightfield;
+mod curve;
+mod aperture;
+mod distortion;
+mod animation;
+mod y4m;
+mod tile;
+mod sampler;
+mod film;
+mod filter;
+mod tonemap;
+mod color;
+mod lut;
+mod aov;
+mod denoise;
+mod checkpoint;
+mod debug;
 
 use std::io::{Error};
 use std::path::PathBuf;
+use std::time::Duration;
 use clap::{Parser, ValueEnum};
+use crate::camera::{FisheyeMapping, StereoLayout};
+use crate::distortion::Distortion;
+use crate::animation::Interpolation;
+use crate::tile::TileOrder;
+use crate::sampler::SamplerKind;
+use crate::filter::FilterKind;
+use crate::color::{ColorSpace, Display};
+use crate::aov::Aov;
+use crate::debug::RenderMode;
 
 #[derive(Parser)]
 #[command(author, version, about)]
 /// A ray tracer written in the Rust programming language
-struct Cli {
+pub struct Cli {
     #[arg(value_enum, default_value_t = Scene::Spheres)]
-    scene: Scene,
+    pub scene: Scene,
     #[arg(short, long, value_name="FILE")]
-    output: Option<PathBuf>
+    pub output: Option<PathBuf>,
+    /// Voxel grid rendered by the smoke scene: a raw voxel file or a directory of slice images
+    #[arg(long, value_name="PATH")]
+    pub volume: Option<PathBuf>,
+    /// Grayscale image rendered by the terrain scene, white being highest
+    #[arg(long, value_name="FILE")]
+    pub heightmap: Option<PathBuf>,
+    /// Replace the scene camera's projection
+    #[arg(long, value_enum)]
+    pub projection: Option<ProjectionKind>,
+    /// Width of the orthographic view in world units
+    #[arg(long, value_name="WIDTH", default_value_t = 10.0, value_parser = parse_positive)]
+    pub view_width: f64,
+    /// Field of view of the fisheye lens from the top edge to the bottom edge, in degrees, up to 360
+    #[arg(long, value_name="DEGREES", default_value_t = 180.0, value_parser = parse_fisheye_fov)]
+    pub fov: f64,
+    /// How the fisheye lens maps angles onto the image
+    #[arg(long, value_enum, default_value_t = FisheyeMapping::Equidistant)]
+    pub fisheye_mapping: FisheyeMapping,
+    /// Render a stereo pair laid out in a single image
+    #[arg(long, value_enum)]
+    pub stereo: Option<StereoLayout>,
+    /// Distance between the stereo cameras, in world units
+    #[arg(long, value_name="DISTANCE", default_value_t = 0.065)]
+    pub interocular_distance: f64,
+    /// Distance at which the stereo images line up [default: the focus distance]
+    #[arg(long, value_name="DISTANCE")]
+    pub convergence_distance: Option<f64>,
+    /// Use a physical camera with this lens focal length, in millimeters
+    #[arg(long, value_name="MM")]
+    pub focal_length: Option<f64>,
+    /// Height of the physical camera's sensor, in millimeters
+    #[arg(long, value_name="MM", default_value_t = 24.0, requires = "focal_length")]
+    pub sensor_height: f64,
+    /// Aperture of the physical camera
+    #[arg(long, value_name="N", default_value_t = 8.0, requires = "focal_length")]
+    pub f_stop: f64,
+    /// Shutter speed of the physical camera, in seconds
+    #[arg(long, value_name="SECONDS", default_value_t = 1.0 / 400.0, requires = "focal_length")]
+    pub shutter_speed: f64,
+    /// Sensitivity of the physical camera
+    #[arg(long, default_value_t = 100.0, requires = "focal_length")]
+    pub iso: f64,
+    /// Focus the physical camera on whatever is under this pixel
+    #[arg(long, value_name="X,Y", value_parser = parse_pixel, requires = "focal_length")]
+    pub autofocus: Option<(i32, i32)>,
+    /// Brighten (or, negative, darken) the image by this many stops
+    #[arg(long, value_name="EV", default_value_t = 0.0, allow_hyphen_values = true)]
+    pub exposure: f64,
+    /// How bright colors are brought into the range a display can show
+    #[arg(long, value_enum, default_value_t = ToneMappingKind::Clip)]
+    pub tone_mapping: ToneMappingKind,
+    /// Scene brightness that extended Reinhard maps to white
+    #[arg(long, value_name="RADIANCE", default_value_t = 4.0, value_parser = parse_positive)]
+    pub white_point: f64,
+    /// Color space to render in
+    #[arg(long, value_enum, default_value_t = ColorSpace::LinearSrgb)]
+    pub working_space: ColorSpace,
+    /// Display to encode the output image for
+    #[arg(long, value_enum, default_value_t = Display::Srgb)]
+    pub display: Display,
+    /// Grade the output with a 3D LUT in .cube format, applied to the display encoded image
+    #[arg(long, value_name="FILE")]
+    pub lut: Option<PathBuf>,
+    /// Give the aperture this many straight blades instead of a round opening
+    #[arg(long, value_name="N", value_parser = clap::value_parser!(u32).range(3..))]
+    pub aperture_blades: Option<u32>,
+    /// Rotation of the aperture blades, in degrees
+    #[arg(long, value_name="DEGREES", default_value_t = 0.0)]
+    pub aperture_rotation: f64,
+    /// Shape the aperture after a grayscale image, white being open
+    #[arg(long, value_name="FILE")]
+    pub aperture_image: Option<PathBuf>,
+    /// How strongly the lens barrel clips out of focus highlights into cat's eyes towards the corners, from 0 to 1
+    #[arg(long, value_name="AMOUNT", default_value_t = 0.0, value_parser = parse_fraction)]
+    pub optical_vignetting: f64,
+    /// Tilt the lens so the plane in focus leans back towards the top of the frame, in degrees
+    #[arg(long, value_name="DEGREES", default_value_t = 0.0, allow_hyphen_values = true)]
+    pub tilt: f64,
+    /// Swing the lens so the plane in focus leans back towards the right of the frame, in degrees
+    #[arg(long, value_name="DEGREES", default_value_t = 0.0, allow_hyphen_values = true)]
+    pub swing: f64,
+    /// Shift the lens to move the framing right, as a fraction of the image width
+    #[arg(long, value_name="FRACTION", default_value_t = 0.0, allow_hyphen_values = true)]
+    pub shift_x: f64,
+    /// Shift the lens to move the framing up, as a fraction of the image height
+    #[arg(long, value_name="FRACTION", default_value_t = 0.0, allow_hyphen_values = true)]
+    pub shift_y: f64,
+    /// Brown-Conrady lens distortion coefficients, as calibration tools report them
+    #[arg(long, value_name="K1[,K2[,K3[,P1,P2]]]", value_parser = parse_distortion, allow_hyphen_values = true)]
+    pub distortion: Option<Distortion>,
+    /// Instead of rendering, remove the --distortion from a photographed plate taken with the scene's camera
+    #[arg(long, value_name="FILE")]
+    pub undistort_plate: Option<PathBuf>,
+    /// Render an image sequence following the camera keyframes in this file, one per line as
+    /// "frame lookfrom_x lookfrom_y lookfrom_z lookat_x lookat_y lookat_z vfov focus_dist".
+    /// Frames are written as numbered files such as out_0001.png, or as one video if the
+    /// output ends in .y4m
+    #[arg(long, value_name="FILE", verbatim_doc_comment)]
+    pub keyframes: Option<PathBuf>,
+    /// How the camera moves between keyframes
+    #[arg(long, value_enum, default_value_t = Interpolation::CatmullRom)]
+    pub interpolation: Interpolation,
+    /// Render an image sequence of this many frames orbiting the camera once around what it looks at
+    #[arg(long, value_name="FRAMES", conflicts_with = "keyframes", value_parser = clap::value_parser!(u32).range(1..))]
+    pub turntable: Option<u32>,
+    /// Frame rate of .y4m videos
+    #[arg(long, value_name="FPS", default_value_t = 24)]
+    pub fps: u32,
+    /// Render only the pixels from X0,Y0 up to but not including X1,Y1, keeping the full frame's camera
+    #[arg(long, value_name="X0,Y0,X1,Y1", value_parser = parse_crop)]
+    pub crop: Option<(i32, i32, i32, i32)>,
+    /// Output the whole image with black outside the --crop window, instead of just the window
+    #[arg(long, requires = "crop")]
+    pub crop_full_frame: bool,
+    /// Width and height of the square tiles the image is rendered in, in pixels
+    #[arg(long, value_name="PIXELS", default_value_t = 32)]
+    pub tile_size: i32,
+    /// Order in which tiles are rendered
+    #[arg(long, value_enum, default_value_t = TileOrder::Spiral)]
+    pub tile_order: TileOrder,
+    /// Seed for every random choice, so renders with the same seed come out identical
+    /// [default: a new seed each run]
+    #[arg(long)]
+    pub seed: Option<u64>,
+    /// How the random numbers of each pixel's samples are spread out
+    #[arg(long, value_enum, default_value_t = SamplerKind::Sobol)]
+    pub sampler: SamplerKind,
+    /// Reconstruction filter that weighs samples into the pixels around them
+    #[arg(long, value_enum, default_value_t = FilterKind::Box)]
+    pub filter: FilterKind,
+    /// Radius of the reconstruction filter in pixels [default: 0.5 for box, 1 for tent, 1.5 for
+    /// gaussian, 2 for mitchell and blackman-harris]
+    #[arg(long, value_name="PIXELS", value_parser = parse_positive)]
+    pub filter_radius: Option<f64>,
+    /// Samples per pixel, or the most any pixel takes with --noise-threshold [default: the scene's]
+    #[arg(long, value_name="N", value_parser = clap::value_parser!(u32).range(1..))]
+    pub samples: Option<u32>,
+    /// Stop sampling a pixel once its estimated error, in display units from 0 to 1 before tone mapping, is below this
+    #[arg(long, value_name="ERROR", value_parser = parse_positive)]
+    pub noise_threshold: Option<f64>,
+    /// Samples every pixel takes before it may stop early
+    #[arg(long, value_name="N", default_value_t = 16, requires = "noise_threshold")]
+    pub min_samples: u32,
+    /// Also save an image of how many samples each pixel took
+    #[arg(long, value_name="FILE")]
+    pub sample_heatmap: Option<PathBuf>,
+    /// Render passes to write along with the image, separated by commas
+    #[arg(long, value_enum, value_delimiter = ',')]
+    pub aovs: Vec<Aov>,
+    /// Where to write the passes: one multi-layer EXR with a linear beauty layer if FILE ends in
+    /// .exr, otherwise an image per pass named like FILE with the pass added, such as aovs.depth.png
+    #[arg(long, value_name="FILE", default_value = "aovs.exr")]
+    pub aov_output: PathBuf,
+    /// Smooth out the noise of the finished image, guided by the albedo, normal and depth passes,
+    /// for usable previews at low sample counts
+    #[arg(long)]
+    pub denoise: bool,
+    /// Show a debug view of the scene's surfaces instead of path tracing it, for finding inverted
+    /// normals, bad uvs and the like
+    #[arg(long, value_enum, default_value_t = RenderMode::Beauty, conflicts_with_all = ["aovs", "denoise", "checkpoint", "resume"])]
+    pub mode: RenderMode,
+    /// Render in passes of one sample per pixel, saving the image so far as it goes, until
+    /// --samples is reached, every pixel is below --noise-threshold or --time runs out
+    #[arg(long, conflicts_with_all = ["keyframes", "turntable"])]
+    pub progressive: bool,
+    /// Time budget of a progressive render, such as 90s, 10m or 8h
+    #[arg(long, value_name="DURATION", value_parser = parse_duration, requires = "progressive")]
+    pub time: Option<Duration>,
+    /// How often a progressive render saves the image so far, and how often --checkpoint is saved
+    /// while rendering
+    #[arg(long, value_name="DURATION", value_parser = parse_duration, default_value = "30s")]
+    pub flush_interval: Duration,
+    /// Save the render state (the seed, a hash of the settings and every pixel's running totals,
+    /// passes included) to FILE every --flush-interval and when done, so it can be carried on with
+    /// --resume
+    #[arg(long, value_name="FILE", conflicts_with_all = ["keyframes", "turntable"])]
+    pub checkpoint: Option<PathBuf>,
+    /// Carry on a render from a checkpoint, adding samples up to --samples. The scene and camera
+    /// settings, --aovs and --denoise included, must match the ones it was started with; the
+    /// checkpoint's seed is used and the checkpoint is kept up to date unless --checkpoint names
+    /// another file
+    #[arg(long, value_name="FILE", conflicts_with_all = ["keyframes", "turntable", "seed"])]
+    pub resume: Option<PathBuf>
 }
 
 #[derive(Clone, ValueEnum)]
@@ -27,10 +240,168 @@ pub enum Scene {
     /// The final scene render of "Ray Tracing in One Weekend"
     Spheres,
     /// A custom scene using an .obj model of Link from the game Ocarina of Time (Work in Progress)
-    Link
+    Link,
+    /// A voxel grid fireball rendered as a heterogeneous volume, or the grid passed with --volume
+    Smoke,
+    /// Constructive solid geometry: a drilled plate, a biconvex lens and a carved die
+    Csg,
+    /// Sphere traced signed distance fields: a Mandelbulb, metaballs and twisted shapes
+    Implicit,
+    /// Procedural hills rendered as a heightfield, or the grayscale image passed with --heightmap
+    Terrain,
+    /// A furry ball and a paint brush made of Bézier curves with a hair material
+    Hair
+}
+
+#[derive(Clone, ValueEnum)]
+pub enum ProjectionKind {
+    /// A pinhole or thin lens camera
+    Perspective,
+    /// Parallel rays, for elevations and plans (see --view-width)
+    Orthographic,
+    /// A circular fisheye lens (see --fov and --fisheye-mapping)
+    Fisheye,
+    /// A 360 degree panorama, rendered at a 2:1 aspect ratio
+    Equirectangular
+}
+
+#[derive(Clone, ValueEnum)]
+pub enum ToneMappingKind {
+    /// None: everything brighter than white clips
+    Clip,
+    /// Reinhard's global operator, which never quite reaches white
+    Reinhard,
+    /// Reinhard's operator reaching white at --white-point
+    ReinhardExtended,
+    /// The ACES filmic curve, contrasty and saturated
+    Aces,
+    /// AgX, which lets very bright colors fade to white without shifting hue
+    Agx,
+    /// John Hable's filmic curve from Uncharted 2
+    Hable
+}
+
+fn parse_pixel(s: &str) -> Result<(i32, i32), String> {
+    match s.split(',').map(|n| n.trim().parse::<i32>()).collect::<Vec<_>>().as_slice() {
+        [Ok(x), Ok(y)] => Ok((*x, *y)),
+        _ => Err(String::from("expected a pixel as X,Y"))
+    }
+}
+
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let (number, unit) = match s.find(|c: char| c.is_alphabetic()) {
+        Some(index) => s.split_at(index),
+        None => (s, "s")
+    };
+    let seconds = match unit {
+        "s" => 1.0,
+        "m" => 60.0,
+        "h" => 3600.0,
+        _ => return Err(format!("unknown unit '{}', expected s, m or h", unit))
+    };
+    match number.parse::<f64>() {
+        Ok(n) if n >= 0.0 => Ok(Duration::from_secs_f64(n * seconds)),
+        _ => Err(String::from("expected a duration such as 90s, 10m or 8h"))
+    }
+}
+fn parse_positive(s: &str) -> Result<f64, String> {
+    match s.trim().parse::<f64>() {
+        Ok(x) if x > 0.0 && x.is_finite() => Ok(x),
+        Ok(_) => Err(String::from("expected a number above 0")),
+        Err(e) => Err(e.to_string())
+    }
+}
+/// A fisheye sees at most the whole sphere around it, which is 360 degrees from edge to edge.
+fn parse_fisheye_fov(s: &str) -> Result<f64, String> {
+    match parse_positive(s)? {
+        fov if fov <= 360.0 => Ok(fov),
+        _ => Err(String::from("a fisheye lens sees at most 360 degrees"))
+    }
+}
+fn parse_fraction(s: &str) -> Result<f64, String> {
+    match s.trim().parse::<f64>() {
+        Ok(x) if (0.0..=1.0).contains(&x) => Ok(x),
+        Ok(_) => Err(String::from("expected a number from 0 to 1")),
+        Err(e) => Err(e.to_string())
+    }
+}
+fn parse_crop(s: &str) -> Result<(i32, i32, i32, i32), String> {
+    match s.split(',').map(|n| n.trim().parse::<i32>()).collect::<Vec<_>>().as_slice() {
+        [Ok(x0), Ok(y0), Ok(x1), Ok(y1)] if x0 < x1 && y0 < y1 => Ok((*x0, *y0, *x1, *y1)),
+        [Ok(_), Ok(_), Ok(_), Ok(_)] => Err(String::from("the crop window is empty")),
+        _ => Err(String::from("expected a window as X0,Y0,X1,Y1"))
+    }
+}
+fn parse_distortion(s: &str) -> Result<Distortion, String> {
+    let coefficients = s.split(',').map(|n| n.trim().parse::<f64>()).collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    let (k1, k2, k3, p1, p2) = match coefficients.as_slice() {
+        [k1] => (*k1, 0.0, 0.0, 0.0, 0.0),
+        [k1, k2] => (*k1, *k2, 0.0, 0.0, 0.0),
+        [k1, k2, k3] => (*k1, *k2, *k3, 0.0, 0.0),
+        [k1, k2, k3, p1, p2] => (*k1, *k2, *k3, *p1, *p2),
+        _ => return Err(String::from("expected K1[,K2[,K3[,P1,P2]]]"))
+    };
+    Ok(Distortion { k1, k2, k3, p1, p2 })
 }
 
 fn main() -> Result<(), Error> {
     let cli = Cli::parse();
-    scene::render(cli.scene, cli.output)
+    scene::render(&cli)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_pixels() {
+        assert_eq!(parse_pixel("640,360"), Ok((640, 360)));
+        assert_eq!(parse_pixel(" 0 , -2 "), Ok((0, -2)));
+        assert!(parse_pixel("640").is_err());
+        assert!(parse_pixel("1,2,3").is_err());
+        assert!(parse_pixel("1.5,2").is_err());
+    }
+
+    #[test]
+    fn parse_durations() {
+        assert_eq!(parse_duration("90s"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("10m"), Ok(Duration::from_secs(600)));
+        assert_eq!(parse_duration("1.5h"), Ok(Duration::from_secs(5400)));
+        assert_eq!(parse_duration(" 45 "), Ok(Duration::from_secs(45)));
+        assert!(parse_duration("3d").is_err());
+        assert!(parse_duration("-1s").is_err());
+        assert!(parse_duration("m").is_err());
+    }
+
+    #[test]
+    fn parse_fisheye_fields_of_view() {
+        assert_eq!(parse_fisheye_fov("180"), Ok(180.0));
+        assert_eq!(parse_fisheye_fov("360"), Ok(360.0));
+        assert!(parse_fisheye_fov("0").is_err());
+        assert!(parse_fisheye_fov("-90").is_err());
+        assert!(parse_fisheye_fov("361").is_err());
+    }
+
+    #[test]
+    fn parse_fractions() {
+        assert_eq!(parse_fraction("0"), Ok(0.0));
+        assert_eq!(parse_fraction("1"), Ok(1.0));
+        assert_eq!(parse_fraction("0.25"), Ok(0.25));
+        assert!(parse_fraction("-0.1").is_err());
+        assert!(parse_fraction("1.5").is_err());
+        assert!(parse_fraction("NaN").is_err());
+    }
+
+    #[test]
+    fn parse_positive_numbers() {
+        assert_eq!(parse_positive("0.5"), Ok(0.5));
+        assert_eq!(parse_positive(" 2 "), Ok(2.0));
+        assert!(parse_positive("0").is_err());
+        assert!(parse_positive("-1").is_err());
+        assert!(parse_positive("inf").is_err());
+        assert!(parse_positive("NaN").is_err());
+        assert!(parse_positive("wide").is_err());
+    }
 }
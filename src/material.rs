@@ -1,5 +1,5 @@
 use std::collections::HashMap;
-use cgmath::{InnerSpace, Vector2, Vector3};
+use cgmath::{ElementWise, InnerSpace, Vector2, Vector3};
 use cgmath::num_traits::Pow;
 use image::{DynamicImage, GenericImageView, Pixel};
 use rand::Rng;
@@ -9,21 +9,26 @@ use crate::hittable::{HitRecord, Hittable};
 use crate::ray::Ray;
 use crate::util;
 use crate::util::{Interval, near_zero, random_unit_vector, reflect, refract, unit_vector};
-
-use Vector2 as Point2;
+use crate::curve::bezier_tangent;
+use crate::color::srgb_to_linear;
 
 pub enum Material {
     Lambertian { albedo: Color3<f64> },
     Texture { key: String, a: Vector2<f64>, b: Vector2<f64>, c: Vector2<f64> },
     Metal { albedo: Color3<f64>, fuzz: f64 },
-    Glass { refraction_index: f64 }
+    Glass { refraction_index: f64 },
+    /// Scatters uniformly in all directions, used by participating media.
+    Isotropic { albedo: Color3<f64>, emission: Color3<f64> },
+    /// A hair fiber, for use on curves. `color` is the fraction of light that survives one pass
+    /// through the fiber and `roughness` in `[0, 1]` widens the highlights.
+    Hair { color: Color3<f64>, roughness: f64 }
 }
 
 impl Material {
-    pub fn scatter(self: &Material, ray: &Ray, hit: &HitRecord, textures: &HashMap<String, DynamicImage>) -> (Color3<f64>, Ray) {
+    pub fn scatter(self: &Material, ray: &Ray, hit: &HitRecord, textures: &HashMap<String, DynamicImage>, rng: &mut impl Rng) -> (Color3<f64>, Ray) {
         match self {
             Material::Lambertian { albedo } => {
-                let mut scatter_direction = hit.normal + util::random_on_hemisphere(&hit.normal);
+                let mut scatter_direction = hit.normal + util::random_on_hemisphere(&hit.normal, rng);
                 if near_zero(scatter_direction) {
                     scatter_direction = hit.normal;
                 }
@@ -39,13 +44,12 @@ impl Material {
                 let reflection = reflect(ray.direction, hit.normal);
                 let new_ray = Ray {
                     origin: hit.point,
-                    direction: reflection + fuzz_range.clamp(*fuzz)*random_unit_vector(),
+                    direction: reflection + fuzz_range.clamp(*fuzz)*random_unit_vector(rng),
                     time: ray.time
                 };
                 (*albedo, new_ray)
             }
             Material::Glass { refraction_index } => {
-                let mut rng = rand::thread_rng();
                 let attenuation = Color3::new(1.0, 1.0, 1.0);
                 let refraction_ratio = if hit.front_face { 1.0 / *refraction_index } else { *refraction_index };
 
@@ -66,34 +70,137 @@ impl Material {
             }
             Material::Texture { key, a: at, b: bt, c: ct} => {
                 let image = textures.get(key).unwrap();
-                match &hit.object {
-                    Hittable::Circle { .. } => { todo!() }
+                let p_uv = match &hit.object {
                     Hittable::Triangle { a: av, b: bv, c: cv, material: _ } => {
                         let p = hit.point;
                         let bary_a = ((bv.y-cv.y)*(p.x-cv.x)+(cv.x-bv.x)*(p.y-cv.y))/((bv.y-cv.y)*(av.x-cv.x)+(cv.x-bv.x)*(av.y-cv.y));
                         let bary_b = ((cv.y-av.y)*(p.x-cv.x)+(av.x-cv.x)*(p.y-cv.y))/((bv.y-cv.y)*(av.x-cv.x)+(cv.x-bv.x)*(av.y-cv.y));
                         let bary_c = 1.0 - bary_a - bary_b;
-                        let p_uv = bary_a * at + bary_b * bt + bary_c * ct;
-                        let height = image.height();
-                        let width = image.width();
-                        let x_pixel = f64::round(p_uv.x * width as f64) as u32;
-                        let y_pixel = f64::round(p_uv.y * height as f64) as u32;
-                        let color = image.get_pixel(x_pixel, height-y_pixel).to_rgb();
-                        let albedo = Color3::new(color.0[0] as f64 / 255.0, color.0[1] as f64 / 255.0, color.0[2] as f64 / 255.0);
-                        let new_mat = Material::Lambertian { albedo };
-                        new_mat.scatter(ray, hit, textures)
+                        bary_a * at + bary_b * bt + bary_c * ct
                     }
-                }
+                    // Other primitives give their own surface coordinates, which the texture repeats over
+                    _ => hit.uv.map(|c| c.rem_euclid(1.0))
+                };
+                let height = image.height();
+                let width = image.width();
+                let x_pixel = (f64::round(p_uv.x * width as f64) as u32).min(width - 1);
+                let y_pixel = f64::round(p_uv.y * height as f64) as u32;
+                let color = image.get_pixel(x_pixel, height.saturating_sub(y_pixel).min(height - 1)).to_rgb();
+                // Texture images are sRGB encoded
+                let albedo = Color3::new(color.0[0] as f64, color.0[1] as f64, color.0[2] as f64).map(|c| srgb_to_linear(c / 255.0));
+                let new_mat = Material::Lambertian { albedo };
+                new_mat.scatter(ray, hit, textures, rng)
+            }
+            Material::Isotropic { albedo, .. } => {
+                let scattered = Ray { origin: hit.point, direction: random_unit_vector(rng), time: ray.time };
+                (*albedo, scattered)
+            }
+            Material::Hair { color, roughness } => {
+                let tangent = match &hit.object {
+                    Hittable::Curve { points, .. } => unit_vector(bezier_tangent(points, hit.uv.x)),
+                    // Other surfaces are treated as combed along any direction lying in them
+                    _ => {
+                        let axis = if hit.normal.x.abs() < 0.9 { Vector3::unit_x() } else { Vector3::unit_y() };
+                        unit_vector(hit.normal.cross(axis))
+                    }
+                };
+                let (attenuation, direction) = Material::sample_hair(ray, hit, tangent, *color, *roughness, rng);
+                (attenuation, Ray { origin: hit.point, direction, time: ray.time })
             }
         }
     }
+    pub fn emitted(self: &Material) -> Color3<f64> {
+        match self {
+            Material::Isotropic { emission, .. } => *emission,
+            _ => Color3::new(0.0, 0.0, 0.0)
+        }
+    }
+    /// Whether the material scatters in a mirror-like lobe rather than diffusely.
+    pub fn is_specular(self: &Material) -> bool {
+        matches!(self, Material::Metal { .. } | Material::Glass { .. })
+    }
+    /// A number identifying the material, the same for materials with the same parameters and
+    /// never 0, which stands for no material.
+    pub fn id(self: &Material) -> u32 {
+        let color_bits = |c: &Color3<f64>| [c.x.to_bits(), c.y.to_bits(), c.z.to_bits()];
+        let (kind, parameters) = match self {
+            Material::Lambertian { albedo } => (1, color_bits(albedo).to_vec()),
+            Material::Texture { key, .. } => (2, key.bytes().map(u64::from).collect()),
+            Material::Metal { albedo, fuzz } => (3, [color_bits(albedo).as_slice(), &[fuzz.to_bits()]].concat()),
+            Material::Glass { refraction_index } => (4, vec![refraction_index.to_bits()]),
+            // The emission of a volume changes from point to point, so only its albedo counts
+            Material::Isotropic { albedo, .. } => (5, color_bits(albedo).to_vec()),
+            Material::Hair { color, roughness } => (6, [color_bits(color).as_slice(), &[roughness.to_bits()]].concat())
+        };
+        let hash = parameters.iter().fold(util::mix_bits(kind), |hash, &bits| util::mix_bits(hash ^ bits));
+        // Kept below 2^24 so it survives being stored as a 32-bit float
+        (hash % 0xffffff) as u32 + 1
+    }
     pub fn clone(self: &Material) -> Material {
         match self {
             Material::Lambertian { albedo } => Material::Lambertian { albedo: *albedo },
             Material::Metal { albedo, fuzz } => Material::Metal { albedo: *albedo, fuzz: *fuzz },
             Material::Glass { refraction_index } => Material::Glass { refraction_index: *refraction_index },
-            Material::Texture { key, a, b, c } => Material::Texture { key: key.clone(), a: *a, b: *b, c: *c}
+            Material::Texture { key, a, b, c } => Material::Texture { key: key.clone(), a: *a, b: *b, c: *c},
+            Material::Isotropic { albedo, emission } => Material::Isotropic { albedo: *albedo, emission: *emission },
+            Material::Hair { color, roughness } => Material::Hair { color: *color, roughness: *roughness }
+        }
+    }
+    /// Samples one of the three main paths light takes through a hair fiber (after Marschner et al.):
+    /// reflection off the surface (R), transmission straight through (TT) and transmission with one
+    /// internal reflection (TRT). Each lobe leaves at the mirror angle along the fiber, tilted by
+    /// the cuticle scales and blurred by `roughness`, and at an angle around the fiber set by where
+    /// the ray struck it.
+    fn sample_hair(ray: &Ray, hit: &HitRecord, tangent: Vector3<f64>, color: Color3<f64>, roughness: f64, rng: &mut impl Rng) -> (Color3<f64>, Vector3<f64>) {
+        const ETA: f64 = 1.55;
+        const CUTICLE_TILT: f64 = 0.035;
+        let incoming = -unit_vector(ray.direction);
+
+        // Frame around the fiber with x pointing back along the incoming direction
+        let sin_theta_i = Interval::new(-1.0, 1.0).clamp(incoming.dot(tangent));
+        let cos_theta_i = f64::sqrt(1.0 - sin_theta_i * sin_theta_i);
+        let across = incoming - sin_theta_i * tangent;
+        let x = if near_zero(across) { hit.normal } else { unit_vector(across) };
+        let y = tangent.cross(x);
+
+        // Offset across the fiber, -1 at one edge and 1 at the other
+        let h = Interval::new(-0.999, 0.999).clamp(2.0 * hit.uv.y - 1.0);
+        let gamma_o = f64::asin(h);
+        let eta_perp = f64::sqrt(ETA * ETA - sin_theta_i * sin_theta_i) / cos_theta_i.max(1e-4);
+        let gamma_t = f64::asin(h / eta_perp);
+
+        // Lobe weights: Fresnel at the surface and absorption along each pass through the fiber
+        let fresnel = Material::reflectance(cos_theta_i * f64::cos(gamma_o), ETA);
+        let pass = color.map(|c| c.powf(2.0 * f64::cos(gamma_t)));
+        let lobes = [
+            (0, Color3::new(fresnel, fresnel, fresnel)),
+            (1, (1.0 - fresnel).pow(2.0) * pass),
+            (2, (1.0 - fresnel).pow(2.0) * fresnel * pass.mul_element_wise(pass))
+        ];
+        let luminance = |c: Color3<f64>| 0.2126 * c.x + 0.7152 * c.y + 0.0722 * c.z;
+        let total: f64 = lobes.iter().map(|(_, weight)| luminance(*weight)).sum();
+        if total <= 0.0 {
+            return (Color3::new(0.0, 0.0, 0.0), incoming);
         }
+        let mut choice = rng.gen_range(0.0..total);
+        let (p, weight) = *lobes.iter()
+            .find(|(_, weight)| { choice -= luminance(*weight); choice <= 0.0 })
+            .unwrap_or(&lobes[2]);
+        let probability = luminance(weight) / total;
+
+        // Longitudinal angle: mirrored along the fiber, shifted by the cuticle and blurred
+        let beta = f64::max(0.02, roughness) * match p { 0 => 1.0, 1 => 0.5, _ => 2.0 };
+        let alpha = CUTICLE_TILT * match p { 0 => -2.0, 1 => 1.0, _ => 4.0 };
+        let gaussian = f64::sqrt(-2.0 * f64::ln(1.0 - rng.gen::<f64>())) * f64::cos(2.0 * std::f64::consts::PI * rng.gen::<f64>());
+        let theta_o = Interval::new(-1.5, 1.5).clamp(-f64::asin(sin_theta_i) + alpha + beta * gaussian);
+
+        // Azimuthal angle: where a specular cylinder would send the ray, blurred by the roughness
+        let p = p as f64;
+        let logistic = { let u: f64 = rng.gen_range(1e-6..1.0 - 1e-6); beta * f64::ln(u / (1.0 - u)) };
+        let phi_o = 2.0 * p * gamma_t - 2.0 * gamma_o + p * std::f64::consts::PI + logistic;
+
+        let direction = theta_o.sin() * tangent + theta_o.cos() * (phi_o.cos() * x + phi_o.sin() * y);
+        (weight / probability, direction)
     }
     fn reflectance(cos: f64, ref_idx: f64) -> f64 {
         let r0 = ((1.0-ref_idx) / (1.0+ref_idx)).pow(2.0);
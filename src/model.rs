@@ -3,21 +3,23 @@ use std::fs::File;
 use std::io::{BufRead, BufReader};
 use std::ops::Deref;
 use cgmath::{Vector3, Vector2};
-use image::{DynamicImage, GenericImageView, ImageResult, Pixel};
+use image::DynamicImage;
 use crate::hittable::Hittable;
 use crate::material::Material;
 
 use Vector3 as Point3;
-use Vector3 as Color3;
 use Vector2 as Point2;
 
 type TClosure = dyn Fn(Point3<f64>) -> Point3<f64>;
 type Transform = Box<TClosure>;
 
 pub struct Model {
+    #[allow(dead_code)]
     pub path: String,
+    #[allow(dead_code)]
     pub vertices: Vec<Point3<f64>>,
-    pub faces: Vec<Hittable>,
+    /// The faces of each group (`g` or `o`) in the file
+    pub meshes: Vec<Vec<Hittable>>,
     pub textures: HashMap<String, DynamicImage>
 }
 impl Model {
@@ -32,7 +34,7 @@ impl Model {
         let input = BufReader::new(File::open(obj_file).unwrap());
         let mut vertices: Vec<Point3<f64>> = vec![];
         let mut texture_coords: Vec<Point2<f64>> = vec![];
-        let mut faces: Vec<Hittable> = vec![];
+        let mut meshes: Vec<Vec<Hittable>> = vec![vec![]];
         let mut textures: HashMap<String, DynamicImage> = HashMap::new();
         let mut mat_name: String = String::new();
         for line in input.lines() {
@@ -44,8 +46,11 @@ impl Model {
                 "v" => {
                     vertices.push(Model::parse_vertex(points, &transforms));
                 }
+                "g" | "o" if meshes.last().is_some_and(|mesh| !mesh.is_empty()) => {
+                    meshes.push(vec![]);
+                }
                 "f" => {
-                    faces.push(Model::parse_face(points, mat_name.clone(), &vertices, &texture_coords));
+                    meshes.last_mut().unwrap().push(Model::parse_face(points, mat_name.clone(), &vertices, &texture_coords));
                 }
                 "vt" => {
                     let x = points.first().unwrap_or(&"").parse::<f64>().unwrap_or(0.0);
@@ -64,7 +69,7 @@ impl Model {
         Self {
             path,
             vertices,
-            faces,
+            meshes,
             textures
         }
     }
@@ -129,13 +134,13 @@ impl MaterialTemplateLibrary {
             let (id, points) = tokenize_line(line.unwrap_or(String::from("#")));
             match id.as_str() {
                 "newmtl" => {
-                    let name = points.get(0);
+                    let name = points.first();
                     if let Some(name_unwrapped) = name {
                         current_material = String::from(name_unwrapped);
                     }
                 },
                 "map_Kd" => {
-                    let file_name = points.get(0);
+                    let file_name = points.first();
                     if let Some(file_name_unwrapped) = file_name {
                         materials.insert(String::from(&current_material), String::from(file_name_unwrapped));
                     }
@@ -1,4 +1,4 @@
-use cgmath::{ElementWise, Vector3, VectorSpace};
+use cgmath::{ElementWise, InnerSpace, Vector3, VectorSpace};
 
 use Vector3 as Point3;
 use crate::util;
@@ -6,6 +6,9 @@ use crate::util;
 use Vector3 as Color3;
 use crate::hittable::{HittableList};
 use crate::util::Interval;
+use crate::color::ColorSpace;
+use crate::aov::AovSample;
+use crate::sampler::Sampler;
 
 pub struct Ray {
     pub origin: Point3<f64>,
@@ -16,21 +19,86 @@ impl Ray {
     pub fn at(self: &Ray, t: f64) -> Point3<f64> {
         self.origin + t*self.direction
     }
-    pub fn color(self: &Ray, hittable_list: &HittableList, max_depth: i32) -> Color3<f64> {
+    /// Radiance arriving along the ray, in `space`. Scene colors are converted into `space` as
+    /// they are used.
+    pub fn color(self: &Ray, hittable_list: &HittableList, max_depth: i32, space: ColorSpace, rng: &mut Sampler) -> Color3<f64> {
         if max_depth <= 0 {
             return Color3::new(0.0, 0.0, 0.0);
         }
-        let hit_record = hittable_list.hit(self, Interval::new(0.001, f64::INFINITY));
+        rng.next_bounce();
+        let hit_record = hittable_list.hit(self, Interval::new(0.001, f64::INFINITY), rng);
         match hit_record {
             Some(hit) => {
-                let (attenuation, scattered) = hit.material.scatter(self, &hit, &hittable_list.textures);
-                attenuation.mul_element_wise(scattered.color(hittable_list, max_depth - 1))
+                let (attenuation, scattered) = hit.material.scatter(self, &hit, &hittable_list.textures, rng);
+                let emitted = space.from_linear_srgb(hit.material.emitted());
+                let attenuation = space.from_linear_srgb(attenuation);
+                emitted + attenuation.mul_element_wise(scattered.color(hittable_list, max_depth - 1, space, rng))
+            }
+            None => self.sky(space)
+        }
+    }
+    /// The sky seen along the ray, in `space`.
+    fn sky(self: &Ray, space: ColorSpace) -> Color3<f64> {
+        let unit_direction = util::unit_vector(self.direction);
+        let a = 0.5 * (unit_direction.y + 1.0);
+        space.from_linear_srgb(Color3::new(1.0, 1.0, 1.0).lerp(Color3::new(0.5, 0.7, 1.0), a))
+    }
+    /// Like `color`, but also records what the ray found at the first surface it hit, with the
+    /// light reflected there split into direct and indirect, diffuse and specular. The color
+    /// returned is the same as `color`'s.
+    pub fn color_with_aovs(self: &Ray, hittable_list: &HittableList, max_depth: i32, space: ColorSpace, rng: &mut Sampler) -> (Color3<f64>, AovSample) {
+        let zero = Color3::new(0.0, 0.0, 0.0);
+        if max_depth <= 0 {
+            return (zero, AovSample::miss(zero));
+        }
+        rng.next_bounce();
+        match hittable_list.hit_object(self, Interval::new(0.001, f64::INFINITY), rng, &mut 0) {
+            Some((object, hit)) => {
+                let (attenuation, scattered) = hit.material.scatter(self, &hit, &hittable_list.textures, rng);
+                let emitted = space.from_linear_srgb(hit.material.emitted());
+                let attenuation = space.from_linear_srgb(attenuation);
+                let (direct, indirect) = scattered.direct_and_indirect(hittable_list, max_depth - 1, space, rng);
+                let color = emitted + attenuation.mul_element_wise(direct + indirect);
+                let (direct, indirect) = (attenuation.mul_element_wise(direct), attenuation.mul_element_wise(indirect));
+                let specular = hit.material.is_specular();
+                let sample = AovSample {
+                    depth: hit.t * self.direction.magnitude(),
+                    position: hit.point,
+                    normal: hit.normal,
+                    albedo: attenuation,
+                    uv: hit.uv,
+                    object_id: object + 1,
+                    material_id: hit.material.id(),
+                    diffuse_direct: if specular { zero } else { direct },
+                    diffuse_indirect: if specular { zero } else { indirect },
+                    specular_direct: if specular { direct } else { zero },
+                    specular_indirect: if specular { indirect } else { zero },
+                    emission: emitted
+                };
+                (color, sample)
             }
             None => {
-                let unit_direction = util::unit_vector(self.direction);
-                let a = 0.5 * (unit_direction.y + 1.0);
-                Color3::new(1.0, 1.0, 1.0).lerp(Color3::new(0.5, 0.7, 1.0), a)
+                let sky = self.sky(space);
+                (sky, AovSample::miss(sky))
             }
         }
     }
-}
\ No newline at end of file
+    /// The light `color` finds along the ray, split into what the first surface hit (or the sky)
+    /// gives off itself and what it reflects.
+    fn direct_and_indirect(self: &Ray, hittable_list: &HittableList, max_depth: i32, space: ColorSpace, rng: &mut Sampler) -> (Color3<f64>, Color3<f64>) {
+        let zero = Color3::new(0.0, 0.0, 0.0);
+        if max_depth <= 0 {
+            return (zero, zero);
+        }
+        rng.next_bounce();
+        match hittable_list.hit(self, Interval::new(0.001, f64::INFINITY), rng) {
+            Some(hit) => {
+                let (attenuation, scattered) = hit.material.scatter(self, &hit, &hittable_list.textures, rng);
+                let emitted = space.from_linear_srgb(hit.material.emitted());
+                let attenuation = space.from_linear_srgb(attenuation);
+                (emitted, attenuation.mul_element_wise(scattered.color(hittable_list, max_depth - 1, space, rng)))
+            }
+            None => (self.sky(space), zero)
+        }
+    }
+}
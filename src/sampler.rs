@@ -0,0 +1,361 @@
+use std::sync::OnceLock;
+use clap::ValueEnum;
+use rand::{Error, RngCore, SeedableRng};
+use rand::rngs::StdRng;
+
+use crate::util::mix_bits;
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum SamplerKind {
+    /// Independent uniform random numbers
+    Independent,
+    /// Jittered points on a grid of about sqrt(samples) by sqrt(samples) cells for each pair of
+    /// dimensions, best with square sample counts
+    Stratified,
+    /// Owen-scrambled Sobol points, different in every pixel
+    Sobol,
+    /// The same Sobol points in every pixel, each pixel offset by a blue noise mask so the
+    /// remaining error looks like fine grain rather than blotches
+    BlueNoise
+}
+
+/// Dimensions set aside for the pixel position, time and lens position.
+const CAMERA_DIMENSIONS: u32 = 8;
+/// Dimensions set aside for each bounce, for finding the hit (volumes draw there) and scattering.
+const BOUNCE_DIMENSIONS: u32 = 8;
+
+/// The random numbers for one sample of one pixel.
+///
+/// Every number drawn is the next dimension of the sample's point, so the pixel position, lens
+/// position, time and each bounce along the path all get their own well spread dimensions. The
+/// camera and each bounce start at a fixed dimension, so a rejection loop that draws more than
+/// usual can't shift the dimensions of later bounces; draws past a bounce's share are plain
+/// random numbers. It is a `RngCore`, so anything that takes a random number generator can draw
+/// from it.
+pub struct Sampler {
+    kind: SamplerKind,
+    seed: u64,
+    /// Hash of the render seed and the pixel
+    pixel_seed: u64,
+    pixel: (u32, u32),
+    index: u32,
+    samples_per_pixel: u32,
+    dimension: u32,
+    /// Where the current bounce's dimensions end
+    dimension_end: u32,
+    /// How many bounces have started
+    bounce: u32,
+    /// Where the independent sampler, and anything else that needs plain random bytes, draws from
+    fallback: StdRng
+}
+impl Sampler {
+    pub fn new(kind: SamplerKind, seed: u64, pixel: (u32, u32), index: u32, samples_per_pixel: u32) -> Self {
+        let pixel_seed = mix_bits(mix_bits(seed ^ pixel.0 as u64) ^ pixel.1 as u64);
+        Sampler {
+            kind,
+            seed,
+            pixel_seed,
+            pixel,
+            index,
+            samples_per_pixel: samples_per_pixel.max(1),
+            dimension: 0,
+            dimension_end: CAMERA_DIMENSIONS,
+            bounce: 0,
+            fallback: StdRng::seed_from_u64(mix_bits(pixel_seed ^ index as u64))
+        }
+    }
+    /// Moves on to the dimensions of the path's next bounce.
+    pub fn next_bounce(self: &mut Sampler) {
+        self.dimension = CAMERA_DIMENSIONS + self.bounce * BOUNCE_DIMENSIONS;
+        self.dimension_end = self.dimension + BOUNCE_DIMENSIONS;
+        self.bounce += 1;
+    }
+    /// The next dimension of this sample's point, in `[0, 1)`.
+    pub fn next_f64(self: &mut Sampler) -> f64 {
+        if self.dimension >= self.dimension_end {
+            return to_unit(self.fallback.next_u32());
+        }
+        let dimension = self.dimension;
+        self.dimension += 1;
+        match self.kind {
+            SamplerKind::Independent => to_unit(self.fallback.next_u32()),
+            SamplerKind::Stratified => self.stratified(dimension),
+            SamplerKind::Sobol => to_unit(scrambled_sobol(self.index, dimension, self.pixel_seed)),
+            SamplerKind::BlueNoise => {
+                // Shift the mask by a different amount for each dimension so they don't line up
+                let shift = mix_bits(dimension as u64);
+                let x = (self.pixel.0 as u64 + shift) as usize % BLUE_NOISE_SIZE;
+                let y = (self.pixel.1 as u64 + (shift >> 32)) as usize % BLUE_NOISE_SIZE;
+                let offset = blue_noise()[y * BLUE_NOISE_SIZE + x];
+                let point = to_unit(scrambled_sobol(self.index, dimension, self.seed));
+                (point + offset).fract()
+            }
+        }
+    }
+    fn stratified(self: &mut Sampler, dimension: u32) -> f64 {
+        let n = (self.samples_per_pixel as f64).sqrt() as u32;
+        // Samples past the last full grid have no cell left
+        if self.index >= n * n {
+            return to_unit(self.fallback.next_u32());
+        }
+        // Pair up dimensions, and give each pair its own shuffle of which sample gets which cell
+        let pair = dimension / 2;
+        let cell = permutation_element(self.index, n * n, mix_bits(self.pixel_seed ^ pair as u64) as u32);
+        let jitter = to_unit(mix_bits(self.pixel_seed ^ ((self.index as u64) << 32 | dimension as u64)) as u32);
+        let stratum = if dimension % 2 == 0 { cell % n } else { cell / n };
+        (stratum as f64 + jitter) / n as f64
+    }
+}
+impl RngCore for Sampler {
+    fn next_u32(&mut self) -> u32 {
+        (self.next_f64() * 4294967296.0) as u32
+    }
+    fn next_u64(&mut self) -> u64 {
+        (self.next_f64() * 18446744073709551616.0) as u64
+    }
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.fallback.fill_bytes(dest)
+    }
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        self.fallback.try_fill_bytes(dest)
+    }
+}
+
+fn to_unit(x: u32) -> f64 {
+    x as f64 / 4294967296.0
+}
+
+/// Sobol direction numbers for the first four dimensions, from Joe and Kuo's tables.
+const SOBOL_DIRECTIONS: [[u32; 32]; 4] = sobol_directions();
+
+const fn sobol_directions() -> [[u32; 32]; 4] {
+    // Degree, coefficients and initial direction numbers of each dimension's primitive polynomial
+    const POLYNOMIALS: [(usize, u32, [u32; 3]); 3] = [(1, 0, [1, 0, 0]), (2, 1, [1, 3, 0]), (3, 1, [1, 3, 1])];
+    let mut directions = [[0; 32]; 4];
+    let mut i = 0;
+    while i < 32 {
+        directions[0][i] = 1 << (31 - i);
+        i += 1;
+    }
+    let mut d = 0;
+    while d < 3 {
+        let (degree, coefficients, initial) = POLYNOMIALS[d];
+        let v = &mut directions[d + 1];
+        let mut i = 0;
+        while i < 32 {
+            if i < degree {
+                v[i] = initial[i] << (31 - i);
+            } else {
+                v[i] = v[i - degree] ^ (v[i - degree] >> degree);
+                let mut k = 1;
+                while k < degree {
+                    if (coefficients >> (degree - 1 - k)) & 1 == 1 {
+                        v[i] ^= v[i - k];
+                    }
+                    k += 1;
+                }
+            }
+            i += 1;
+        }
+        d += 1;
+    }
+    directions
+}
+
+/// Dimension `dimension` of point `index` of a shuffled, Owen-scrambled Sobol sequence (after
+/// Burley, "Practical Hash-based Owen Scrambling"). Dimensions come in blocks of four, each with
+/// its own shuffle of the points, so there are as many as a path needs.
+fn scrambled_sobol(index: u32, dimension: u32, seed: u64) -> u32 {
+    let block_seed = mix_bits(seed ^ (dimension / 4) as u64);
+    let index = nested_uniform_scramble(index, block_seed as u32);
+    let directions = &SOBOL_DIRECTIONS[(dimension % 4) as usize];
+    let mut point = 0;
+    let mut bits = index;
+    let mut bit = 0;
+    while bits != 0 {
+        if bits & 1 == 1 {
+            point ^= directions[bit];
+        }
+        bits >>= 1;
+        bit += 1;
+    }
+    nested_uniform_scramble(point, mix_bits(block_seed ^ (dimension % 4 + 1) as u64) as u32)
+}
+
+/// Owen scrambling done by hashing: each bit is flipped depending only on the bits above it.
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    let mut x = x.reverse_bits();
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x.reverse_bits()
+}
+
+/// Element `i` of a random permutation of `0..length` chosen by `seed`, without building the
+/// permutation (Kensler, "Correlated Multi-Jittered Sampling").
+fn permutation_element(mut i: u32, length: u32, seed: u32) -> u32 {
+    let mut w = length - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= seed >> 16;
+        i ^= (i & w) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= seed >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < length {
+            break;
+        }
+    }
+    (i.wrapping_add(seed)) % length
+}
+
+const BLUE_NOISE_SIZE: usize = 64;
+
+/// A tileable mask of blue noise thresholds in `[0, 1)`, built once by void-and-cluster
+/// (Ulichney, 1993): points are added one at a time, each where the pattern so far leaves the
+/// largest gap, and numbered in the order they went in.
+fn blue_noise() -> &'static [f64] {
+    static MASK: OnceLock<Vec<f64>> = OnceLock::new();
+    MASK.get_or_init(|| {
+        let n = BLUE_NOISE_SIZE;
+        let count = n * n;
+        // How crowded each cell is: the sum of a Gaussian around every point placed so far,
+        // wrapping around the edges so the mask tiles
+        let mut kernel = vec![0.0; count];
+        for dy in 0..n {
+            for dx in 0..n {
+                let (x, y) = (dx.min(n - dx) as f64, dy.min(n - dy) as f64);
+                kernel[dy * n + dx] = f64::exp(-(x * x + y * y) / (2.0 * 1.5 * 1.5));
+            }
+        }
+        let mut energy = vec![0.0; count];
+        let mut placed = vec![false; count];
+        let mut rank = vec![0; count];
+        let add = |energy: &mut [f64], cell: usize, sign: f64| {
+            let (x, y) = (cell % n, cell / n);
+            for j in 0..n {
+                for i in 0..n {
+                    energy[j * n + i] += sign * kernel[((j + n - y) % n) * n + (i + n - x) % n];
+                }
+            }
+        };
+
+        // Start from a random tenth of the cells, then move the most crowded point into the
+        // largest gap until that stops changing anything, or for at most one move per cell in case
+        // points keep swapping back and forth
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut initial = 0;
+        while initial < count / 10 {
+            let cell = rng.next_u32() as usize % count;
+            if !placed[cell] {
+                placed[cell] = true;
+                add(&mut energy, cell, 1.0);
+                initial += 1;
+            }
+        }
+        let tightest = |energy: &[f64], placed: &[bool], want: bool| {
+            (0..count).filter(|&cell| placed[cell] == want)
+                .max_by(|&a, &b| if want { energy[a].total_cmp(&energy[b]) } else { energy[b].total_cmp(&energy[a]) })
+                .unwrap()
+        };
+        for _ in 0..count {
+            let cluster = tightest(&energy, &placed, true);
+            placed[cluster] = false;
+            add(&mut energy, cluster, -1.0);
+            let void = tightest(&energy, &placed, false);
+            placed[void] = true;
+            add(&mut energy, void, 1.0);
+            if void == cluster {
+                break;
+            }
+        }
+
+        // Number the starting points by taking the most crowded away first...
+        let (mut first_energy, mut first_placed) = (energy.clone(), placed.clone());
+        for r in (0..count / 10).rev() {
+            let cluster = tightest(&first_energy, &first_placed, true);
+            first_placed[cluster] = false;
+            add(&mut first_energy, cluster, -1.0);
+            rank[cluster] = r;
+        }
+        // ...then the rest by filling the largest gap each time
+        for r in count / 10..count {
+            let void = tightest(&energy, &placed, false);
+            placed[void] = true;
+            add(&mut energy, void, 1.0);
+            rank[void] = r;
+        }
+        rank.into_iter().map(|r| (r as f64 + 0.5) / count as f64).collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn samples_stay_in_the_unit_interval() {
+        for kind in SamplerKind::value_variants() {
+            for pixel in [(0, 0), (17, 3), (1919, 1079)] {
+                for index in [0, 1, 15, 16, 255] {
+                    let mut sampler = Sampler::new(*kind, 7, pixel, index, 16);
+                    for _ in 0..12 {
+                        let x = sampler.next_f64();
+                        assert!((0.0..1.0).contains(&x), "{x} is out of range");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn blue_noise_ranks_every_cell_once() {
+        let mask = blue_noise();
+        assert_eq!(mask.len(), BLUE_NOISE_SIZE * BLUE_NOISE_SIZE);
+        let mut ranks = mask.iter().map(|&value| {
+            assert!((0.0..1.0).contains(&value), "{value} is out of range");
+            (value * mask.len() as f64) as usize
+        }).collect::<Vec<usize>>();
+        ranks.sort_unstable();
+        assert!(ranks.iter().enumerate().all(|(i, &rank)| i == rank));
+    }
+
+    #[test]
+    fn later_bounces_stay_stratified_after_uneven_draws() {
+        let mut cells = [0; 16];
+        for index in 0..16 {
+            let mut sampler = Sampler::new(SamplerKind::Sobol, 7, (3, 5), index, 16);
+            // Rejection loops draw a different amount for each sample
+            for _ in 0..index % 5 + 2 {
+                sampler.next_f64();
+            }
+            sampler.next_bounce();
+            for _ in 0..index % 3 {
+                sampler.next_f64();
+            }
+            sampler.next_bounce();
+            let (x, y) = (sampler.next_f64(), sampler.next_f64());
+            cells[(y * 4.0) as usize * 4 + (x * 4.0) as usize] += 1;
+        }
+        assert_eq!(cells, [1; 16]);
+    }
+}
@@ -1,60 +1,288 @@
 use std::fs::File;
-use std::io::Error;
-use std::path::PathBuf;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use cgmath::Vector3;
-use crate::camera::{Camera, CameraConfig};
+use image::{DynamicImage, RgbImage};
+use rand::{thread_rng, Rng, SeedableRng};
+use rand::rngs::StdRng;
+use crate::camera::{AdaptiveSampling, Camera, CameraConfig, Crop, PhysicalCamera, Projection, Stereo, TiltShift};
 
 use Vector3 as Point3;
 use Vector3 as Color3;
 use crate::hittable::{Hittable, HittableList};
 use crate::material::Material;
 use crate::model::Model;
-use crate::Scene;
+use crate::volume::VoxelGrid;
+use crate::heightfield::Heightfield;
+use crate::aperture::Aperture;
+use crate::animation::{frame_path, Animation};
+use crate::y4m::Y4mWriter;
+use crate::checkpoint::Checkpoint;
+use crate::filter::{Filter, FilterKind};
+use crate::tonemap::ToneMapping;
+use crate::lut::Lut;
+use crate::aov;
+use crate::denoise::{denoise, GUIDES};
+use crate::util::mix_bits;
+use crate::{Cli, ProjectionKind, Scene, ToneMappingKind};
 
-pub fn render(scene: Scene, output: Option<PathBuf>) -> Result<(), Error> {
-    let output_file = File::create(output.unwrap_or(PathBuf::from("out.ppm")))?;
-    match scene {
-        Scene::Spheres => {
-            render_spheres(output_file)
+pub fn render(cli: &Cli) -> Result<(), Error> {
+    let resumed = cli.resume.as_deref().map(Checkpoint::open).transpose()?;
+    let seed = match &resumed {
+        Some(checkpoint) => checkpoint.seed,
+        None => cli.seed.unwrap_or_else(|| thread_rng().gen())
+    };
+    let mut rng = StdRng::seed_from_u64(seed);
+    let (mut config, world) = match cli.scene {
+        Scene::Spheres => spheres(&mut rng),
+        Scene::Link => link(),
+        Scene::Smoke => {
+            let grid = match &cli.volume {
+                Some(path) => VoxelGrid::open(path)?,
+                None => VoxelGrid::fireball(64)
+            };
+            smoke(grid)
         }
-        Scene::Link => {
-            render_link(output_file)
+        Scene::Csg => csg(),
+        Scene::Implicit => implicit(),
+        Scene::Terrain => {
+            let field = match &cli.heightmap {
+                Some(path) => Heightfield::from_image(path)?,
+                None => Heightfield::hills(1025)
+            };
+            terrain(field)
         }
+        Scene::Hair => hair(&mut rng)
+    };
+    config.seed = seed;
+    if let Some(samples) = cli.samples {
+        config.samples_per_pixel = samples;
     }
-}
-fn render_spheres(mut output_image: File) -> Result<(), Error> {
-    let camera = Camera::initialize(
-        CameraConfig {
-            aspect_ratio: 16.0 / 9.0,
-            image_width: 1200,
-            samples_per_pixel: 500,
-            max_depth: 50,
-            vfov: 15.0,
-            lookfrom: Point3::new(13.0, 2.0, 3.0),
-            lookat: Point3::new(0.0, 0.0, 0.0),
-            vup: Vector3::new(0.0, 1.0, 0.0),
-            defocus_angle: 0.6,
-            focus_dist: 10.0
+    if let Some(noise_threshold) = cli.noise_threshold {
+        config.adaptive = Some(AdaptiveSampling { min_samples: cli.min_samples, noise_threshold });
+    }
+    config.sampler = cli.sampler;
+    config.mode = cli.mode;
+    config.aovs = cli.aovs.clone();
+    if cli.denoise {
+        for guide in GUIDES {
+            if !config.aovs.contains(&guide) {
+                config.aovs.push(guide);
+            }
         }
-    );
-    let world = HittableList::random_spheres();
-    camera.render(&mut output_image, &world)
-}
-fn render_link(mut output_image: File) -> Result<(), Error> {
-    let camera = Camera::initialize(
-        CameraConfig {
-            aspect_ratio: 16.0 / 9.0,
-            image_width: 1200,
-            samples_per_pixel: 500,
-            max_depth: 50,
-            vfov: 15.0,
-            lookfrom: Point3::new(13.0, 2.0, 3.0),
-            lookat: Point3::new(0.0, 1.0, 0.0),
-            vup: Vector3::new(0.0, 1.0, 0.0),
-            defocus_angle: 0.6,
-            focus_dist: 13.37
+    }
+    config.filter = Filter { kind: cli.filter, radius: cli.filter_radius.unwrap_or(cli.filter.default_radius()) };
+    // Narrower than a pixel, a box leaves pixels that no sample lands in
+    if matches!(config.filter.kind, FilterKind::Box) && config.filter.radius < 0.5 {
+        return Err(Error::new(ErrorKind::InvalidInput, "--filter-radius must be at least 0.5 for the box filter"));
+    }
+    if let Some(projection) = &cli.projection {
+        config.projection = match projection {
+            ProjectionKind::Perspective => Projection::Perspective,
+            ProjectionKind::Orthographic => Projection::Orthographic { view_width: cli.view_width },
+            ProjectionKind::Fisheye => Projection::Fisheye { fov: cli.fov, mapping: cli.fisheye_mapping },
+            ProjectionKind::Equirectangular => {
+                // A full panorama covers twice as many degrees across as it does top to bottom
+                config.aspect_ratio = 2.0;
+                Projection::Equirectangular
+            }
+        };
+    }
+    if let Some(layout) = cli.stereo {
+        config.stereo = Some(Stereo {
+            layout,
+            interocular_distance: cli.interocular_distance,
+            convergence_distance: cli.convergence_distance
+        });
+    }
+    if let Some(focal_length) = cli.focal_length {
+        config.physical = Some(PhysicalCamera {
+            focal_length,
+            sensor_height: cli.sensor_height,
+            f_stop: cli.f_stop,
+            shutter_speed: cli.shutter_speed,
+            iso: cli.iso,
+            autofocus: cli.autofocus
+        });
+    }
+    config.exposure_compensation = cli.exposure;
+    config.tone_mapping = match cli.tone_mapping {
+        ToneMappingKind::Clip => ToneMapping::Clip,
+        ToneMappingKind::Reinhard => ToneMapping::Reinhard,
+        ToneMappingKind::ReinhardExtended => ToneMapping::ReinhardExtended { white_point: cli.white_point },
+        ToneMappingKind::Aces => ToneMapping::AcesFilmic,
+        ToneMappingKind::Agx => ToneMapping::Agx,
+        ToneMappingKind::Hable => ToneMapping::Hable
+    };
+    config.working_space = cli.working_space;
+    config.display = cli.display;
+    if let Some(path) = &cli.lut {
+        config.lut = Some(Arc::new(Lut::open(path)?));
+    }
+    if let Some(path) = &cli.aperture_image {
+        config.aperture = Aperture::from_image(path)?;
+    } else if let Some(blades) = cli.aperture_blades {
+        config.aperture = Aperture::Polygon { blades, rotation: cli.aperture_rotation };
+    }
+    config.optical_vignetting = cli.optical_vignetting;
+    if cli.tilt != 0.0 || cli.swing != 0.0 || cli.shift_x != 0.0 || cli.shift_y != 0.0 {
+        config.tilt_shift = Some(TiltShift { tilt: cli.tilt, swing: cli.swing, shift_x: cli.shift_x, shift_y: cli.shift_y });
+    }
+    if cli.distortion.is_some() {
+        config.distortion = cli.distortion;
+    }
+    config.tile_size = cli.tile_size;
+    config.tile_order = cli.tile_order;
+    if let Some((x0, y0, x1, y1)) = cli.crop {
+        config.crop = Some(Crop { x0, y0, x1, y1, full_frame: cli.crop_full_frame });
+    }
+    let animation = match (&cli.keyframes, cli.turntable) {
+        (Some(path), _) => Some(Animation::open(path, cli.interpolation)?),
+        (None, Some(frames)) => Some(Animation::Turntable { frames }),
+        (None, None) => None
+    };
+    if let Some(animation) = animation {
+        let output = cli.output.clone().unwrap_or(PathBuf::from("out.png"));
+        // A .y4m output holds the whole sequence as one video instead of numbered images
+        let is_video = output.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("y4m"));
+        let mut video: Option<Y4mWriter> = None;
+        for frame in animation.frames() {
+            let mut frame_config = config.clone();
+            animation.apply(frame, &mut frame_config);
+            let camera = focused_camera(frame_config, &world)?;
+            let mut films = camera.new_films();
+            camera.render(&world, &mut films);
+            if cli.denoise {
+                films.iter_mut().for_each(denoise);
+            }
+            let film = camera.compose(&films);
+            if let Some(path) = &cli.sample_heatmap {
+                save(&film.heatmap(camera.max_samples()), &frame_path(path, frame))?;
+            }
+            if !cli.aovs.is_empty() {
+                aov::save(&camera, &film, &frame_path(&cli.aov_output, frame))?;
+            }
+            let image = camera.to_image(&film);
+            if !is_video {
+                save(&image, &frame_path(&output, frame))?;
+                continue;
+            }
+            let writer = match &mut video {
+                Some(writer) => writer,
+                None => video.insert(Y4mWriter::create(&output, image.width(), image.height(), cli.fps)?)
+            };
+            writer.write_frame(&image)?;
         }
-    );
+        return Ok(());
+    }
+    let output = cli.output.clone().unwrap_or(PathBuf::from("out.ppm"));
+    let camera = focused_camera(config, &world)?;
+    if let Some(path) = &cli.undistort_plate {
+        let plate = image::open(path).map_err(|e| Error::new(ErrorKind::InvalidData, e))?.to_rgb32f();
+        let undistorted = DynamicImage::from(camera.undistort_plate(&plate)).to_rgb8();
+        return save(&undistorted, &output);
+    }
+    let settings = settings_fingerprint(cli, &camera)?;
+    let mut films = match resumed {
+        Some(checkpoint) if checkpoint.settings != settings || !camera.fits(&checkpoint.films) =>
+            return Err(Error::new(ErrorKind::InvalidData, "checkpoint was rendered with different settings")),
+        Some(checkpoint) => checkpoint.films,
+        None => camera.new_films()
+    };
+    let checkpoint = cli.checkpoint.as_ref().or(cli.resume.as_ref());
+    if cli.progressive {
+        camera.render_progressive(&world, &mut films, cli.time, cli.flush_interval, |films| {
+            if let Some(path) = checkpoint {
+                Checkpoint::save(path, seed, settings, films)?;
+            }
+            let mut films = films.to_vec();
+            if cli.denoise {
+                films.iter_mut().for_each(denoise);
+            }
+            camera.write_ppm(&camera.compose(&films), &mut File::create(&output)?)
+        })?;
+    } else if let Some(path) = checkpoint {
+        camera.render_in_batches(&world, &mut films, cli.flush_interval, |films| Checkpoint::save(path, seed, settings, films))?;
+    } else {
+        camera.render(&world, &mut films);
+    }
+    if let Some(path) = checkpoint {
+        Checkpoint::save(path, seed, settings, &films)?;
+    }
+    if cli.denoise {
+        films.iter_mut().for_each(denoise);
+    }
+    let film = camera.compose(&films);
+    if let Some(path) = &cli.sample_heatmap {
+        save(&film.heatmap(camera.max_samples()), path)?;
+    }
+    if !cli.aovs.is_empty() {
+        aov::save(&camera, &film, &cli.aov_output)?;
+    }
+    let mut output_file = File::create(output)?;
+    camera.write_ppm(&film, &mut output_file)
+}
+/// A hash of the scene, the files it was loaded from and every camera setting that changes its
+/// samples, which a checkpoint must have been rendered with to be carried on.
+fn settings_fingerprint(cli: &Cli, camera: &Camera) -> Result<u64, Error> {
+    let hash = mix_bits(camera.fingerprint() ^ cli.scene.clone() as u64);
+    [&cli.volume, &cli.heightmap].into_iter().flatten().try_fold(hash, |hash, path| hash_contents(hash, path))
+}
+/// `hash` with the contents of the file at `path` folded in, or of every file in it, in name
+/// order, if it is a directory.
+fn hash_contents(hash: u64, path: &Path) -> Result<u64, Error> {
+    if path.is_dir() {
+        let mut paths = std::fs::read_dir(path)?.map(|entry| entry.map(|e| e.path())).collect::<Result<Vec<_>, Error>>()?;
+        paths.sort();
+        return paths.iter().try_fold(hash, |hash, path| hash_contents(hash, path));
+    }
+    let bytes = std::fs::read(path)?;
+    let hash = mix_bits(hash ^ bytes.len() as u64);
+    Ok(bytes.chunks(8).fold(hash, |hash, chunk| {
+        let mut word = [0; 8];
+        word[..chunk.len()].copy_from_slice(chunk);
+        mix_bits(hash ^ u64::from_le_bytes(word))
+    }))
+}
+fn save(image: &RgbImage, path: &Path) -> Result<(), Error> {
+    image.save(path).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+}
+/// The camera for `config`, focused on the autofocus pixel's subject if it has one. Fails if there
+/// is nothing under that pixel or nothing inside the crop window.
+fn focused_camera(mut config: CameraConfig, world: &HittableList) -> Result<Camera, Error> {
+    if let Some((i, j)) = config.physical.and_then(|physical| physical.autofocus) {
+        let depth = Camera::initialize(config.clone()).depth_at(i, j, world)
+            .map_err(|e| Error::new(e.kind(), format!("--autofocus: {}", e)))?;
+        let Some(depth) = depth else {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("--autofocus: nothing to focus on at pixel {},{}", i, j)));
+        };
+        config.focus_dist = depth;
+    }
+    let camera = Camera::initialize(config);
+    camera.check_window()?;
+    Ok(camera)
+}
+fn spheres(rng: &mut impl Rng) -> (CameraConfig, HittableList) {
+    let config = CameraConfig {
+        vfov: 15.0,
+        lookfrom: Point3::new(13.0, 2.0, 3.0),
+        lookat: Point3::new(0.0, 0.0, 0.0),
+        defocus_angle: 0.6,
+        ..Default::default()
+    };
+    let world = HittableList::random_spheres(rng);
+    (config, world)
+}
+fn link() -> (CameraConfig, HittableList) {
+    let config = CameraConfig {
+        vfov: 15.0,
+        lookfrom: Point3::new(13.0, 2.0, 3.0),
+        lookat: Point3::new(0.0, 1.0, 0.0),
+        defocus_angle: 0.6,
+        focus_dist: 13.37,
+        ..Default::default()
+    };
     let model = Model::new(
         String::from("./models/zelda"),
         String::from("Link Adult"),
@@ -63,7 +291,10 @@ fn render_link(mut output_image: File) -> Result<(), Error> {
             scale(0.01)
         ]
     );
-    let mut world = HittableList{ hittables: model.faces, textures: model.textures };
+    let mut world = HittableList { hittables: vec![], objects: vec![], textures: model.textures };
+    for mesh in model.meshes {
+        world.add_object(mesh);
+    }
     world.add(
         Hittable::Circle {
             center: Vector3::new(0.0, -1000.0, 0.0),
@@ -71,7 +302,57 @@ fn render_link(mut output_image: File) -> Result<(), Error> {
             material: Material::Metal { albedo: Color3::new(0.5, 0.5, 0.5), fuzz: 0.5 }
         }
     );
-    camera.render(&mut output_image, &world)
+    (config, world)
+}
+fn smoke(grid: VoxelGrid) -> (CameraConfig, HittableList) {
+    let config = CameraConfig {
+        vfov: 30.0,
+        lookfrom: Point3::new(0.0, 3.0, 10.0),
+        lookat: Point3::new(0.0, 1.5, 0.0),
+        ..Default::default()
+    };
+    let world = HittableList::smoke(grid);
+    (config, world)
+}
+fn csg() -> (CameraConfig, HittableList) {
+    let config = CameraConfig {
+        vfov: 35.0,
+        lookfrom: Point3::new(2.0, 5.0, 8.0),
+        lookat: Point3::new(0.0, 0.5, 0.0),
+        ..Default::default()
+    };
+    let world = HittableList::csg();
+    (config, world)
+}
+fn implicit() -> (CameraConfig, HittableList) {
+    let config = CameraConfig {
+        vfov: 40.0,
+        lookfrom: Point3::new(0.0, 2.5, 7.0),
+        lookat: Point3::new(0.0, 0.9, 0.0),
+        ..Default::default()
+    };
+    let world = HittableList::implicit();
+    (config, world)
+}
+fn terrain(field: Heightfield) -> (CameraConfig, HittableList) {
+    let config = CameraConfig {
+        vfov: 45.0,
+        lookfrom: Point3::new(0.0, 6.0, 14.0),
+        lookat: Point3::new(0.0, 0.5, 0.0),
+        ..Default::default()
+    };
+    let world = HittableList::terrain(field);
+    (config, world)
+}
+fn hair(rng: &mut impl Rng) -> (CameraConfig, HittableList) {
+    let config = CameraConfig {
+        vfov: 30.0,
+        lookfrom: Point3::new(0.0, 2.5, 7.0),
+        lookat: Point3::new(0.0, 0.7, 0.0),
+        ..Default::default()
+    };
+    let world = HittableList::hair(rng);
+    (config, world)
 }
 
 #[allow(dead_code)]
@@ -0,0 +1,89 @@
+use cgmath::{InnerSpace, Vector3};
+
+use Vector3 as Point3;
+use crate::util::vector_length;
+
+/// A composable signed distance field: negative inside a surface, positive outside.
+pub enum Sdf {
+    Sphere { center: Point3<f64>, radius: f64 },
+    Box { center: Point3<f64>, half_extents: Vector3<f64> },
+    /// A torus lying in the xz plane around `center`.
+    Torus { center: Point3<f64>, major_radius: f64, minor_radius: f64 },
+    /// The power-8 style Mandelbulb fractal, scaled to roughly fill a sphere of `scale` around `center`.
+    Mandelbulb { center: Point3<f64>, scale: f64, power: f64, iterations: u32 },
+    Union { a: Box<Sdf>, b: Box<Sdf> },
+    /// A union that blends the two surfaces together within a distance of `k`.
+    SmoothUnion { a: Box<Sdf>, b: Box<Sdf>, k: f64 },
+    /// Infinitely repeats `child`, which should fit inside one cell of size `period` centered on the origin.
+    Repeat { period: Vector3<f64>, child: Box<Sdf> },
+    /// Twists `child` around the y axis by `rate` radians per unit of height.
+    Twist { rate: f64, child: Box<Sdf> },
+    Translate { offset: Vector3<f64>, child: Box<Sdf> }
+}
+impl Sdf {
+    pub fn distance(self: &Sdf, p: Point3<f64>) -> f64 {
+        match self {
+            Sdf::Sphere { center, radius } => vector_length(p - center) - radius,
+            Sdf::Box { center, half_extents } => {
+                let local = p - center;
+                let q = Vector3::new(local.x.abs(), local.y.abs(), local.z.abs()) - half_extents;
+                let outside = Vector3::new(q.x.max(0.0), q.y.max(0.0), q.z.max(0.0));
+                vector_length(outside) + q.x.max(q.y.max(q.z)).min(0.0)
+            }
+            Sdf::Torus { center, major_radius, minor_radius } => {
+                let local = p - center;
+                let ring = f64::sqrt(local.x * local.x + local.z * local.z) - major_radius;
+                f64::sqrt(ring * ring + local.y * local.y) - minor_radius
+            }
+            Sdf::Mandelbulb { center, scale, power, iterations } => {
+                let c = (p - center) / *scale;
+                let mut z = c;
+                let mut dr = 1.0;
+                let mut r = 0.0;
+                for _ in 0..*iterations {
+                    r = vector_length(z);
+                    if r > 2.0 {
+                        break;
+                    }
+                    let theta = f64::acos(z.z / r) * power;
+                    let phi = f64::atan2(z.y, z.x) * power;
+                    dr = r.powf(power - 1.0) * power * dr + 1.0;
+                    z = r.powf(*power) * Vector3::new(theta.sin() * phi.cos(), phi.sin() * theta.sin(), theta.cos()) + c;
+                }
+                0.5 * r.ln() * r / dr * scale
+            }
+            Sdf::Union { a, b } => a.distance(p).min(b.distance(p)),
+            Sdf::SmoothUnion { a, b, k } => {
+                let da = a.distance(p);
+                let db = b.distance(p);
+                let h = (0.5 + 0.5 * (db - da) / k).clamp(0.0, 1.0);
+                db + (da - db) * h - k * h * (1.0 - h)
+            }
+            Sdf::Repeat { period, child } => {
+                let wrap = |x: f64, period: f64| x - period * (x / period).round();
+                child.distance(Point3::new(wrap(p.x, period.x), wrap(p.y, period.y), wrap(p.z, period.z)))
+            }
+            Sdf::Twist { rate, child } => {
+                let angle = rate * p.y;
+                let (sin, cos) = angle.sin_cos();
+                let twisted = Point3::new(cos * p.x - sin * p.z, p.y, sin * p.x + cos * p.z);
+                // Twisting stretches space, so shrink the step by the local stretch factor
+                let stretch = f64::sqrt(1.0 + (rate * f64::sqrt(p.x * p.x + p.z * p.z)).powi(2));
+                child.distance(twisted) / stretch
+            }
+            Sdf::Translate { offset, child } => child.distance(p - offset)
+        }
+    }
+    /// The surface normal at `p`, estimated from the field's gradient with four samples.
+    pub fn normal(self: &Sdf, p: Point3<f64>) -> Vector3<f64> {
+        let h = 1e-5;
+        let k0 = Vector3::new(1.0, -1.0, -1.0);
+        let k1 = Vector3::new(-1.0, -1.0, 1.0);
+        let k2 = Vector3::new(-1.0, 1.0, -1.0);
+        let k3 = Vector3::new(1.0, 1.0, 1.0);
+        (k0 * self.distance(p + k0 * h)
+            + k1 * self.distance(p + k1 * h)
+            + k2 * self.distance(p + k2 * h)
+            + k3 * self.distance(p + k3 * h)).normalize()
+    }
+}
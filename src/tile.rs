@@ -0,0 +1,132 @@
+use clap::ValueEnum;
+
+/// A rectangle of pixels rendered as one unit of work, from `(x0, y0)` up to but not including
+/// `(x1, y1)`.
+#[derive(Clone, Copy)]
+pub struct Tile {
+    pub x0: i32,
+    pub y0: i32,
+    pub x1: i32,
+    pub y1: i32
+}
+impl Tile {
+    /// The tile's pixels, row by row.
+    pub fn pixels(self: &Tile) -> impl Iterator<Item = (i32, i32)> {
+        let (x0, x1) = (self.x0, self.x1);
+        (self.y0..self.y1).flat_map(move |j| (x0..x1).map(move |i| (i, j)))
+    }
+}
+
+/// The order tiles are handed out in, which is the order they appear in while rendering.
+#[derive(Clone, Copy, ValueEnum)]
+pub enum TileOrder {
+    /// Left to right, top to bottom
+    Scanline,
+    /// Outwards from the center of the image, where the subject usually is
+    Spiral,
+    /// Along a Hilbert curve, so consecutive tiles are always neighbours and share cached geometry
+    Hilbert
+}
+
+/// Splits the window from `(x0, y0)` to `(x1, y1)` into tiles of at most `size` by `size` pixels,
+/// in the given order.
+pub fn tiles(x0: i32, y0: i32, x1: i32, y1: i32, size: i32, order: TileOrder) -> Vec<Tile> {
+    let size = size.max(1);
+    let columns = (x1 - x0 + size - 1) / size;
+    let rows = (y1 - y0 + size - 1) / size;
+    let mut grid = vec![];
+    for row in 0..rows {
+        for column in 0..columns {
+            grid.push((column, row));
+        }
+    }
+    match order {
+        TileOrder::Scanline => {}
+        TileOrder::Spiral => {
+            // Ring by ring around the central tile, each ring swept by angle
+            let (cx, cy) = ((columns - 1) as f64 / 2.0, (rows - 1) as f64 / 2.0);
+            let key = |&(column, row): &(i32, i32)| {
+                let (dx, dy) = (column as f64 - cx, row as f64 - cy);
+                (dx.abs().max(dy.abs()), dy.atan2(dx))
+            };
+            grid.sort_by(|a, b| {
+                let (ring_a, angle_a) = key(a);
+                let (ring_b, angle_b) = key(b);
+                ring_a.total_cmp(&ring_b).then(angle_a.total_cmp(&angle_b))
+            });
+        }
+        TileOrder::Hilbert => {
+            let n = (columns.max(rows).max(1) as u32).next_power_of_two();
+            grid.sort_by_key(|&(column, row)| hilbert_index(n, column as u32, row as u32));
+        }
+    }
+    grid.into_iter().map(|(column, row)| Tile {
+        x0: x0 + column * size,
+        y0: y0 + row * size,
+        x1: (x0 + (column + 1) * size).min(x1),
+        y1: (y0 + (row + 1) * size).min(y1)
+    }).collect()
+}
+
+/// Distance of cell `(x, y)` along the Hilbert curve filling an `n` by `n` grid, `n` being a
+/// power of two.
+fn hilbert_index(n: u32, mut x: u32, mut y: u32) -> u64 {
+    let mut index = 0;
+    let mut s = n / 2;
+    while s > 0 {
+        let rx = (x & s > 0) as u32;
+        let ry = (y & s > 0) as u32;
+        index += s as u64 * s as u64 * ((3 * rx) ^ ry) as u64;
+        // Rotate the quadrant so the curve inside it starts and ends next to its neighbours
+        if ry == 0 {
+            if rx == 1 {
+                x = s - 1 - (x & (s - 1));
+                y = s - 1 - (y & (s - 1));
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s /= 2;
+    }
+    index
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hilbert_curve_visits_neighbours_in_turn() {
+        let n = 8;
+        let mut cells = vec![None; (n * n) as usize];
+        for y in 0..n {
+            for x in 0..n {
+                let index = hilbert_index(n, x, y) as usize;
+                assert!(cells[index].is_none(), "two cells at index {index}");
+                cells[index] = Some((x as i32, y as i32));
+            }
+        }
+        let cells = cells.into_iter().map(Option::unwrap).collect::<Vec<(i32, i32)>>();
+        for pair in cells.windows(2) {
+            let ((x0, y0), (x1, y1)) = (pair[0], pair[1]);
+            assert_eq!((x1 - x0).abs() + (y1 - y0).abs(), 1, "{:?} is not next to {:?}", pair[0], pair[1]);
+        }
+    }
+
+    #[test]
+    fn tiles_cover_every_pixel_once() {
+        let (x0, y0, x1, y1) = (3, 5, 70, 42);
+        for order in TileOrder::value_variants() {
+            for size in [1, 7, 16, 100] {
+                let mut counts = vec![0; ((x1 - x0) * (y1 - y0)) as usize];
+                for tile in tiles(x0, y0, x1, y1, size, *order) {
+                    assert!(tile.x1 - tile.x0 <= size && tile.y1 - tile.y0 <= size);
+                    for (i, j) in tile.pixels() {
+                        assert!((x0..x1).contains(&i) && (y0..y1).contains(&j));
+                        counts[((j - y0) * (x1 - x0) + i - x0) as usize] += 1;
+                    }
+                }
+                assert!(counts.iter().all(|&count| count == 1));
+            }
+        }
+    }
+}
@@ -0,0 +1,92 @@
+use cgmath::Vector3;
+
+use Vector3 as Color3;
+use crate::film::luminance;
+use crate::color::transform;
+
+/// How scene radiance, which has no upper limit, is squeezed into the `[0, 1]` a display shows.
+#[derive(Clone, Copy)]
+pub enum ToneMapping {
+    /// Leave colors alone, so anything brighter than 1 clips to white
+    Clip,
+    Reinhard,
+    /// Reinhard, but reaching white at `white_point` instead of never
+    ReinhardExtended { white_point: f64 },
+    AcesFilmic,
+    Agx,
+    Hable
+}
+impl ToneMapping {
+    /// The linear display color for the linear Rec. 709 scene color `color`.
+    pub fn apply(self: &ToneMapping, color: Color3<f64>) -> Color3<f64> {
+        match self {
+            ToneMapping::Clip => color,
+            // Compress the luminance and scale the color with it, which keeps hues
+            ToneMapping::Reinhard => scale_luminance(color, |l| l / (1.0 + l)),
+            ToneMapping::ReinhardExtended { white_point } => {
+                let white_squared = white_point * white_point;
+                scale_luminance(color, |l| l * (1.0 + l / white_squared) / (1.0 + l))
+            }
+            ToneMapping::AcesFilmic => aces_filmic(color),
+            ToneMapping::Agx => agx(color),
+            ToneMapping::Hable => {
+                const WHITE: f64 = 11.2;
+                let curve = |x: f64| {
+                    let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+                    (x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f) - e / f
+                };
+                color.map(|c| curve(2.0 * c.max(0.0)) / curve(WHITE))
+            }
+        }
+    }
+}
+
+fn scale_luminance(color: Color3<f64>, curve: impl Fn(f64) -> f64) -> Color3<f64> {
+    let l = luminance(color);
+    if l <= 0.0 { Color3::new(0.0, 0.0, 0.0) } else { color * (curve(l) / l) }
+}
+
+/// The ACES reference rendering and output transforms for an sRGB display, as fitted by Stephen
+/// Hill: into the ACES working space, through the fitted curve, and back out.
+fn aces_filmic(color: Color3<f64>) -> Color3<f64> {
+    const INPUT: [[f64; 3]; 3] = [
+        [0.59719, 0.35458, 0.04823],
+        [0.07600, 0.90834, 0.01566],
+        [0.02840, 0.13383, 0.83777]
+    ];
+    const OUTPUT: [[f64; 3]; 3] = [
+        [1.60475, -0.53108, -0.07367],
+        [-0.10208, 1.10813, -0.00605],
+        [-0.00327, -0.07276, 1.07602]
+    ];
+    let v = transform(&INPUT, color);
+    let fitted = v.map(|x| (x * (x + 0.0245786) - 0.000090537) / (x * (0.983729 * x + 0.4329510) + 0.238081));
+    transform(&OUTPUT, fitted).map(|x| x.clamp(0.0, 1.0))
+}
+
+/// Troy Sobotka's AgX, using Benjamin Wrensch's polynomial fit of its base contrast curve: the
+/// color is pulled in towards gray, encoded logarithmically, put through the curve and pushed back
+/// out. Very bright colors fade to white instead of clipping to a different hue.
+fn agx(color: Color3<f64>) -> Color3<f64> {
+    const INSET: [[f64; 3]; 3] = [
+        [0.842479062253094, 0.0784335999999992, 0.0792237451477643],
+        [0.0423282422610123, 0.878468636469772, 0.0791661274605434],
+        [0.0423756549057051, 0.0784336, 0.879142973793104]
+    ];
+    const OUTSET: [[f64; 3]; 3] = [
+        [1.19687900512017, -0.0980208811401368, -0.0990297440797205],
+        [-0.0528968517574562, 1.15190312990417, -0.0989611768448433],
+        [-0.0529716355144438, -0.0980434501171241, 1.15107367264116]
+    ];
+    // The range of exposures encoded, in stops around middle gray
+    const MIN_EV: f64 = -12.47393;
+    const MAX_EV: f64 = 4.026069;
+    let encoded = transform(&INSET, color).map(|x| (x.max(1e-10).log2().clamp(MIN_EV, MAX_EV) - MIN_EV) / (MAX_EV - MIN_EV));
+    let curved = encoded.map(|x| {
+        let x2 = x * x;
+        let x4 = x2 * x2;
+        15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232
+    });
+    // The curve's output is display encoded with a 2.2 gamma
+    transform(&OUTSET, curved).map(|x| x.max(0.0).powf(2.2))
+}
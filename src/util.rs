@@ -4,16 +4,23 @@ use cgmath::num_traits::Pow;
 use cgmath::{InnerSpace, Vector3};
 use rand::Rng;
 
+use Vector3 as Point3;
 use Vector3 as Color3;
+use crate::ray::Ray;
 
-pub fn random_in_unit_disc() -> Vector3<f64> {
-    let mut rng = rand::thread_rng();
-    loop {
-        let p = Vector3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), 0.0);
-        if vector_length_squared(p) < 1.0 {
-            return p;
-        }
-    }
+/// A uniform point in the unit disc, drawing exactly two random numbers so that well spread
+/// samples stay well spread.
+pub fn random_in_unit_disc(rng: &mut impl Rng) -> Vector3<f64> {
+    let r = f64::sqrt(rng.gen::<f64>());
+    let theta = 2.0 * std::f64::consts::PI * rng.gen::<f64>();
+    Vector3::new(r * theta.cos(), r * theta.sin(), 0.0)
+}
+/// The finalizer of SplitMix64, which spreads every bit of `x` over every bit of the result.
+pub fn mix_bits(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e3779b97f4a7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
 }
 pub fn refract(uv: Vector3<f64>, normal: Vector3<f64>, etai_over_etat: f64) -> Vector3<f64> {
     // gonna be real with you, i don't understand a lick of this math.
@@ -33,35 +40,38 @@ pub fn near_zero(v: Vector3<f64>) -> bool {
     let s = 1e-8;
     (f64::abs(v.x) < s) && (f64::abs(v.y) < s) && (f64::abs(v.z) < s)
 }
-pub fn random_on_hemisphere(normal: &Vector3<f64>) -> Vector3<f64> {
-    let on_hemisphere = random_unit_vector();
+pub fn random_on_hemisphere(normal: &Vector3<f64>, rng: &mut impl Rng) -> Vector3<f64> {
+    let on_hemisphere = random_unit_vector(rng);
     if normal.dot(on_hemisphere) > 0.0 {
         on_hemisphere
     } else {
         -on_hemisphere
     }
 }
-pub fn random_unit_vector() -> Vector3<f64> {
-    unit_vector(random_vector_in_unit_sphere())
+/// A uniform direction, drawing exactly two random numbers.
+pub fn random_unit_vector(rng: &mut impl Rng) -> Vector3<f64> {
+    let z = 1.0 - 2.0 * rng.gen::<f64>();
+    let r = f64::sqrt(f64::max(0.0, 1.0 - z * z));
+    let phi = 2.0 * std::f64::consts::PI * rng.gen::<f64>();
+    Vector3::new(r * phi.cos(), r * phi.sin(), z)
 }
-pub fn random_vector_in_unit_sphere() -> Vector3<f64> {
+#[allow(dead_code)]
+pub fn random_vector_in_unit_sphere(rng: &mut impl Rng) -> Vector3<f64> {
     loop {
-        let v = random_vector_bounded(-1.0, 1.0);
+        let v = random_vector_bounded(-1.0, 1.0, rng);
         if vector_length_squared(v) < 1.0 {
             return v;
         }
     }
 }
-pub fn random_vector_bounded(min: f64, max: f64) -> Vector3<f64> {
-    let mut rng = rand::thread_rng();
+pub fn random_vector_bounded(min: f64, max: f64, rng: &mut impl Rng) -> Vector3<f64> {
     let x = rng.gen_range(min..max);
     let y = rng.gen_range(min..max);
     let z = rng.gen_range(min..max);
     Vector3::new(x, y, z)
 }
-#[allow(dead_code)]
-pub fn random_vector() -> Vector3<f64> {
-    random_vector_bounded(0.0, 1.0)
+pub fn random_vector(rng: &mut impl Rng) -> Vector3<f64> {
+    random_vector_bounded(0.0, 1.0, rng)
 }
 pub fn unit_vector(v: Vector3<f64>) -> Vector3<f64> {
     let length = vector_length(v);
@@ -73,6 +83,50 @@ pub fn vector_length(v: Vector3<f64>) -> f64 {
 pub fn vector_length_squared(v: Vector3<f64>) -> f64 {
     v.x.pow(2) + v.y.pow(2) + v.z.pow(2)
 }
+/// Returns the entry and exit distances of `ray` through the axis aligned box between `min` and `max`.
+pub fn ray_box_intersection(ray: &Ray, min: Point3<f64>, max: Point3<f64>) -> Option<(f64, f64)> {
+    let mut t_enter = f64::NEG_INFINITY;
+    let mut t_exit = f64::INFINITY;
+    for axis in 0..3 {
+        let inverse = 1.0 / ray.direction[axis];
+        let mut t0 = (min[axis] - ray.origin[axis]) * inverse;
+        let mut t1 = (max[axis] - ray.origin[axis]) * inverse;
+        if inverse < 0.0 {
+            std::mem::swap(&mut t0, &mut t1);
+        }
+        t_enter = f64::max(t_enter, t0);
+        t_exit = f64::min(t_exit, t1);
+        if t_exit <= t_enter {
+            return None;
+        }
+    }
+    Some((t_enter, t_exit))
+}
+/// Approximate emitted radiance of a blackbody at `kelvin`, relative to one at 1500K.
+///
+/// The hue follows Tanner Helland's fit of the Planckian locus and the brightness follows the
+/// Stefan-Boltzmann law, so hotter regions of a fire are both whiter and much brighter.
+pub fn blackbody(kelvin: f64) -> Color3<f64> {
+    let t = Interval::new(1000.0, 40000.0).clamp(kelvin) / 100.0;
+    let r = if t <= 66.0 { 255.0 } else { 329.698727446 * (t - 60.0).powf(-0.1332047592) };
+    let g = if t <= 66.0 {
+        99.4708025861 * t.ln() - 161.1195681661
+    } else {
+        288.1221695283 * (t - 60.0).powf(-0.0755148492)
+    };
+    let b = if t >= 66.0 {
+        255.0
+    } else if t <= 19.0 {
+        0.0
+    } else {
+        138.5177312231 * (t - 10.0).ln() - 305.0447927307
+    };
+    let channel = Interval::new(0.0, 255.0);
+    let hue = Color3::new(channel.clamp(r), channel.clamp(g), channel.clamp(b)) / 255.0;
+    // Un-gamma the fit so it can be used as linear radiance
+    let linear = Color3::new(hue.x * hue.x, hue.y * hue.y, hue.z * hue.z);
+    linear * (kelvin / 1500.0).pow(4)
+}
 pub struct Interval {
     pub min: f64,
     pub max: f64
@@ -102,19 +156,17 @@ impl Interval {
     #[allow(dead_code)]
     pub const WORLD: Interval = Interval { min: f64::NEG_INFINITY, max: f64::INFINITY };
 }
-pub fn write_pixel(file: &mut File, color: &Color3<f64>, samples_per_pixel: i32) -> Result<(), Error> {
-    let scaled = color / samples_per_pixel as f64;
-    let r = linear_to_gamma(scaled.x);
-    let g = linear_to_gamma(scaled.y);
-    let b = linear_to_gamma(scaled.z);
-    let intensity = Interval::new(0.0, 0.999);
-    let ir: i32 = (256.0 * intensity.clamp(r)) as i32;
-    let ig: i32 = (256.0 * intensity.clamp(g)) as i32;
-    let ib: i32 = (256.0 * intensity.clamp(b)) as i32;
+pub fn write_pixel(file: &mut File, color: &Color3<f64>) -> Result<(), Error> {
+    let [ir, ig, ib] = pixel_bytes(color);
     file.write_all(format!("{0} {1} {2}\n", ir, ig, ib).as_bytes())
 }
-fn linear_to_gamma(i: f64) -> f64 {
-    f64::sqrt(i)
+/// The 8-bit value of a `color` already encoded for the display.
+pub fn pixel_bytes(color: &Color3<f64>) -> [u8; 3] {
+    let intensity = Interval::new(0.0, 0.999);
+    let ir = (256.0 * intensity.clamp(color.x)) as u8;
+    let ig = (256.0 * intensity.clamp(color.y)) as u8;
+    let ib = (256.0 * intensity.clamp(color.z)) as u8;
+    [ir, ig, ib]
 }
 
 
@@ -0,0 +1,146 @@
+use std::fs;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read};
+use std::path::Path;
+use cgmath::Vector3;
+
+use Vector3 as Point3;
+
+/// A dense grid of density (and optionally temperature) samples spanning the unit cube.
+///
+/// Samples are stored with x varying fastest, then y, then z.
+pub struct VoxelGrid {
+    pub nx: usize,
+    pub ny: usize,
+    pub nz: usize,
+    pub density: Vec<f32>,
+    pub temperature: Option<Vec<f32>>,
+    pub max_density: f64
+}
+impl VoxelGrid {
+    pub fn new(nx: usize, ny: usize, nz: usize, density: Vec<f32>, temperature: Option<Vec<f32>>) -> Self {
+        let max_density = density.iter().fold(0.0f32, |max, d| max.max(*d)) as f64;
+        Self { nx, ny, nz, density, temperature, max_density }
+    }
+    /// Loads a grid from either a raw voxel file or a directory of slice images.
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        if path.is_dir() {
+            VoxelGrid::read_slices(path)
+        } else {
+            VoxelGrid::read_raw(path)
+        }
+    }
+    /// Reads a raw voxel file.
+    ///
+    /// The file starts with a text header line `VOXELS <nx> <ny> <nz> [temperature]` followed by
+    /// `nx*ny*nz` little-endian `f32` densities and, if the header says so, as many temperatures
+    /// in Kelvin.
+    pub fn read_raw(path: &Path) -> Result<Self, Error> {
+        let file = File::open(path)?;
+        let length = file.metadata()?.len();
+        let mut input = BufReader::new(file);
+        let mut header = String::new();
+        input.read_line(&mut header)?;
+        let tokens = header.split_whitespace().collect::<Vec<&str>>();
+        if tokens.first() != Some(&"VOXELS") || tokens.len() < 4 {
+            return Err(Error::new(ErrorKind::InvalidData, "expected a 'VOXELS nx ny nz' header"));
+        }
+        let dimension = |token: &str| token.parse::<usize>()
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e));
+        let nx = dimension(tokens[1])?;
+        let ny = dimension(tokens[2])?;
+        let nz = dimension(tokens[3])?;
+        let has_temperature = tokens.get(4) == Some(&"temperature");
+        // A damaged header could claim any size, so check the file holds that many voxels before
+        // making room for them
+        let count = nx.checked_mul(ny).and_then(|n| n.checked_mul(nz)).unwrap_or(usize::MAX);
+        let channels = if has_temperature { 2 } else { 1 };
+        if count == 0 || (count as u64).saturating_mul(4 * channels) > length {
+            return Err(Error::new(ErrorKind::InvalidData, format!("{} doesn't hold the {}x{}x{} voxels its header claims", path.display(), nx, ny, nz)));
+        }
+        let density = read_f32s(&mut input, count)?;
+        let temperature = if has_temperature { Some(read_f32s(&mut input, count)?) } else { None };
+        Ok(VoxelGrid::new(nx, ny, nz, density, temperature))
+    }
+    /// Reads a directory of equally sized grayscale images, one per z slice in file name order.
+    pub fn read_slices(dir: &Path) -> Result<Self, Error> {
+        let mut paths = fs::read_dir(dir)?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<Result<Vec<_>, Error>>()?;
+        paths.sort();
+        if paths.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidData, format!("{} has no slice images", dir.display())));
+        }
+        let mut density: Vec<f32> = vec![];
+        let (mut nx, mut ny) = (0, 0);
+        for path in &paths {
+            let slice = image::open(path).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+            if density.is_empty() {
+                (nx, ny) = (slice.width() as usize, slice.height() as usize);
+            } else if (slice.width() as usize, slice.height() as usize) != (nx, ny) {
+                return Err(Error::new(ErrorKind::InvalidData, format!("slice {} has a different size", path.display())));
+            }
+            let luma = slice.to_luma32f();
+            // Image rows run top to bottom, grid rows run bottom to top
+            for y in (0..ny).rev() {
+                for x in 0..nx {
+                    density.push(luma.get_pixel(x as u32, y as u32).0[0]);
+                }
+            }
+        }
+        Ok(VoxelGrid::new(nx, ny, paths.len(), density, None))
+    }
+    /// A procedural fireball: a noisy sphere of smoke with a hot core.
+    pub fn fireball(n: usize) -> Self {
+        let mut density = Vec::with_capacity(n * n * n);
+        let mut temperature = Vec::with_capacity(n * n * n);
+        for z in 0..n {
+            for y in 0..n {
+                for x in 0..n {
+                    let p = Point3::new(x as f64, y as f64, z as f64) / (n - 1) as f64 * 2.0 - Point3::new(1.0, 1.0, 1.0);
+                    let r = f64::sqrt(p.x * p.x + p.y * p.y + p.z * p.z);
+                    let ripple = 0.15 * (f64::sin(9.0 * p.x) * f64::sin(7.0 * p.y + 1.3) * f64::sin(8.0 * p.z + 0.7));
+                    let falloff = f64::max(0.0, 1.0 - (r + ripple) / 0.9);
+                    density.push((4.0 * falloff) as f32);
+                    temperature.push((800.0 + 2200.0 * falloff * falloff) as f32);
+                }
+            }
+        }
+        VoxelGrid::new(n, n, n, density, Some(temperature))
+    }
+    /// Trilinearly interpolated density at `p` in grid space, where the grid spans `[0, 1]^3`.
+    pub fn density(self: &VoxelGrid, p: Point3<f64>) -> f64 {
+        self.lookup(&self.density, p)
+    }
+    /// Trilinearly interpolated temperature in Kelvin, if the grid carries one.
+    pub fn temperature(self: &VoxelGrid, p: Point3<f64>) -> Option<f64> {
+        self.temperature.as_ref().map(|temperature| self.lookup(temperature, p))
+    }
+    fn lookup(self: &VoxelGrid, values: &[f32], p: Point3<f64>) -> f64 {
+        // Voxel centers sit at (i + 0.5) / n
+        let gx = p.x * self.nx as f64 - 0.5;
+        let gy = p.y * self.ny as f64 - 0.5;
+        let gz = p.z * self.nz as f64 - 0.5;
+        let (x0, fx) = (gx.floor(), gx - gx.floor());
+        let (y0, fy) = (gy.floor(), gy - gy.floor());
+        let (z0, fz) = (gz.floor(), gz - gz.floor());
+        let mut value = 0.0;
+        for (dz, wz) in [(0, 1.0 - fz), (1, fz)] {
+            for (dy, wy) in [(0, 1.0 - fy), (1, fy)] {
+                for (dx, wx) in [(0, 1.0 - fx), (1, fx)] {
+                    let x = (x0 as i64 + dx).clamp(0, self.nx as i64 - 1) as usize;
+                    let y = (y0 as i64 + dy).clamp(0, self.ny as i64 - 1) as usize;
+                    let z = (z0 as i64 + dz).clamp(0, self.nz as i64 - 1) as usize;
+                    value += wx * wy * wz * values[(z * self.ny + y) * self.nx + x] as f64;
+                }
+            }
+        }
+        value
+    }
+}
+
+fn read_f32s(input: &mut impl Read, count: usize) -> Result<Vec<f32>, Error> {
+    let mut bytes = vec![0u8; count * 4];
+    input.read_exact(&mut bytes)?;
+    Ok(bytes.chunks_exact(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect())
+}
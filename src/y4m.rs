@@ -0,0 +1,60 @@
+use std::fs::File;
+use std::io::{BufWriter, Error, ErrorKind, Write};
+use std::path::Path;
+use image::RgbImage;
+
+/// Writes frames into an uncompressed YUV4MPEG2 video, which players and encoders read directly.
+///
+/// Frames are stored as 8-bit BT.601 video-range 4:2:0, the format every Y4M reader supports.
+pub struct Y4mWriter {
+    output: BufWriter<File>,
+    width: u32,
+    height: u32
+}
+impl Y4mWriter {
+    pub fn create(path: &Path, width: u32, height: u32, fps: u32) -> Result<Self, Error> {
+        let mut output = BufWriter::new(File::create(path)?);
+        output.write_all(format!("YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C420jpeg\n", width, height, fps).as_bytes())?;
+        Ok(Y4mWriter { output, width, height })
+    }
+    pub fn write_frame(self: &mut Y4mWriter, image: &RgbImage) -> Result<(), Error> {
+        if image.dimensions() != (self.width, self.height) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("every frame of a video must be {}x{}, not {}x{}", self.width, self.height, image.width(), image.height())
+            ));
+        }
+        let (width, height) = (self.width, self.height);
+        let mut luma = Vec::with_capacity((width * height) as usize);
+        for pixel in image.pixels() {
+            let [r, g, b] = pixel.0.map(|c| c as f64 / 255.0);
+            luma.push((16.0 + 65.481 * r + 128.553 * g + 24.966 * b).round() as u8);
+        }
+        // Each chroma sample covers a 2x2 block of pixels, averaged
+        let (chroma_width, chroma_height) = (width.div_ceil(2), height.div_ceil(2));
+        let mut cb = Vec::with_capacity((chroma_width * chroma_height) as usize);
+        let mut cr = Vec::with_capacity((chroma_width * chroma_height) as usize);
+        for cy in 0..chroma_height {
+            for cx in 0..chroma_width {
+                let (mut r, mut g, mut b, mut count) = (0.0, 0.0, 0.0, 0.0);
+                for y in (2 * cy)..(2 * cy + 2).min(height) {
+                    for x in (2 * cx)..(2 * cx + 2).min(width) {
+                        let pixel = image.get_pixel(x, y).0;
+                        r += pixel[0] as f64 / 255.0;
+                        g += pixel[1] as f64 / 255.0;
+                        b += pixel[2] as f64 / 255.0;
+                        count += 1.0;
+                    }
+                }
+                let (r, g, b) = (r / count, g / count, b / count);
+                cb.push((128.0 - 37.797 * r - 74.203 * g + 112.0 * b).round() as u8);
+                cr.push((128.0 + 112.0 * r - 93.786 * g - 18.214 * b).round() as u8);
+            }
+        }
+        self.output.write_all(b"FRAME\n")?;
+        self.output.write_all(&luma)?;
+        self.output.write_all(&cb)?;
+        self.output.write_all(&cr)?;
+        self.output.flush()
+    }
+}